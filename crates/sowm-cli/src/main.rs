@@ -1,8 +1,14 @@
 use clap::{Parser, Subcommand};
 use interprocess::local_socket::{prelude::*, GenericFilePath, Stream};
-use std::io::{BufReader, Read, Write};
+use std::{
+    io::{BufReader, Read, Write},
+    path::PathBuf,
+};
 
-use sowm_common::{init, packet::Packet, ClientMessage, Init, ServerMessage};
+use sowm_common::{
+    packet::Packet, socket_file, ClientMessage, DuplicateImage, MonitorHistory, ServerMessage,
+    Status, MAX_RATING,
+};
 
 #[derive(Debug, Parser)]
 struct Cli {
@@ -14,22 +20,149 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Start cycling wallpapers
-    Start,
+    Start {
+        /// Monitor to start, all monitors if not given
+        #[arg(short, long)]
+        monitor: Option<usize>,
+    },
     /// Stop cycling wallpapers
-    Stop,
+    Stop {
+        /// Monitor to stop, all monitors if not given
+        #[arg(short, long)]
+        monitor: Option<usize>,
+    },
     /// Go to next images
-    Next,
-    /// Update the daemon with the config file
+    Next {
+        /// Monitor to change, all monitors if not given
+        #[arg(short, long)]
+        monitor: Option<usize>,
+    },
+    /// Go back to the previous images
+    Prev {
+        /// Monitor to go back on, all monitors if not given
+        #[arg(short, long)]
+        monitor: Option<usize>,
+    },
+    /// Show the images that have been shown recently
+    History,
+    /// Show a specific image now
+    Set {
+        /// Monitor to show the image on, all monitors if not given
+        #[arg(short, long)]
+        monitor: Option<usize>,
+        /// Keep the image on the monitor when cycling until it is unpinned
+        #[arg(short, long)]
+        pin: bool,
+        /// Image to show
+        path: PathBuf,
+    },
+    /// Let a pinned monitor cycle again
+    Unpin {
+        /// Monitor to unpin, all monitors if not given
+        #[arg(short, long)]
+        monitor: Option<usize>,
+    },
+    /// Mark the current image as a favourite, giving it the highest rating
+    Fav {
+        /// Monitor showing the image
+        #[arg(short, long, default_value_t = 0)]
+        monitor: usize,
+    },
+    /// Rate the current image, higher rated images are shown more often with the weighted order
+    Rate {
+        /// Monitor showing the image
+        #[arg(short, long, default_value_t = 0)]
+        monitor: usize,
+        /// Rating from 1 to 5
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=MAX_RATING as i64))]
+        rating: u8,
+    },
+    /// Remove the rating of the current image
+    Unrate {
+        /// Monitor showing the image
+        #[arg(short, long, default_value_t = 0)]
+        monitor: usize,
+    },
+    /// Never show the current image again and skip to the next one
+    Ban {
+        /// Monitor showing the image
+        #[arg(short, long, default_value_t = 0)]
+        monitor: usize,
+    },
+    /// Manage banned images
+    Bans {
+        #[command(subcommand)]
+        command: BansCommand,
+    },
+    /// Show what the daemon is doing
+    Status,
+    /// Manage collections of images
+    Collection {
+        #[command(subcommand)]
+        command: CollectionCommand,
+    },
+    /// Make the daemon read the config file and scan the images again
     Update,
+    /// List groups of images that are copies of each other
+    Dupes,
+}
+
+#[derive(Debug, Subcommand)]
+enum BansCommand {
+    /// List banned images
+    List,
+    /// Allow a banned image to be shown again
+    Remove {
+        /// Path of the banned image, as shown by `bans list`
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CollectionCommand {
+    /// List collections, marking the one being shown
+    List,
+    /// Show images from a collection until a different schedule rule applies
+    Use {
+        /// Name of the collection
+        name: String,
+    },
 }
 
 impl Command {
-    fn to_client_message(self, init: Init) -> ClientMessage {
+    fn into_client_message(self) -> ClientMessage {
         match self {
-            Command::Start => ClientMessage::Start,
-            Command::Stop => ClientMessage::Stop,
-            Command::Next => ClientMessage::Next,
-            Command::Update => ClientMessage::Update(init),
+            Command::Start { monitor } => ClientMessage::Start { monitor },
+            Command::Stop { monitor } => ClientMessage::Stop { monitor },
+            Command::Next { monitor } => ClientMessage::Next { monitor },
+            Command::Prev { monitor } => ClientMessage::Previous { monitor },
+            Command::History => ClientMessage::History,
+            Command::Set { monitor, pin, path } => ClientMessage::Set {
+                monitor,
+                // The daemon doesn't share our working directory
+                path: std::path::absolute(&path).unwrap_or(path),
+                pin,
+            },
+            Command::Unpin { monitor } => ClientMessage::Unpin { monitor },
+            Command::Fav { monitor } => ClientMessage::Favourite { monitor },
+            Command::Rate { monitor, rating } => ClientMessage::Rate { monitor, rating },
+            Command::Unrate { monitor } => ClientMessage::Unrate { monitor },
+            Command::Ban { monitor } => ClientMessage::Ban { monitor },
+            Command::Bans {
+                command: BansCommand::List,
+            } => ClientMessage::Bans,
+            Command::Bans {
+                command: BansCommand::Remove { path },
+            } => ClientMessage::Unban(std::path::absolute(&path).unwrap_or(path)),
+            Command::Status => ClientMessage::Status,
+            Command::Collection {
+                command: CollectionCommand::List,
+            } => ClientMessage::Collections,
+            Command::Collection {
+                command: CollectionCommand::Use { name },
+            } => ClientMessage::UseCollection(name),
+            Command::Update => ClientMessage::Reload,
+            Command::Dupes => ClientMessage::Duplicates,
         }
     }
 }
@@ -37,19 +170,19 @@ impl Command {
 fn main() {
     let cli = Cli::parse();
 
-    let init = match init() {
+    // The daemon has the config and images, so only the socket is needed
+    let path = match socket_file() {
         Err(e) => panic!("Init Error: {e}"),
         Ok(v) => v,
     };
 
-    let path = &init.socket_file;
     let name = path.as_path().to_fs_name::<GenericFilePath>().unwrap();
 
     let conn = Stream::connect(name).unwrap();
     let mut conn = BufReader::new(conn);
 
     // Send message
-    let message: ClientMessage = cli.command.to_client_message(init);
+    let message: ClientMessage = cli.command.into_client_message();
     let data = message.serialize().unwrap();
     let packet = Packet::new(data);
     let bytes = packet.into_bytes();
@@ -63,5 +196,86 @@ fn main() {
     conn.read_exact(&mut buf).unwrap();
     let message: ServerMessage = ServerMessage::deserialize(&buf).unwrap();
 
-    println!("Server: {message:#?}");
+    match message {
+        ServerMessage::History(monitors) => print_history(&monitors),
+        ServerMessage::Bans(bans) => {
+            for ban in bans {
+                println!("{}", ban.path.display());
+            }
+        }
+        ServerMessage::Status(status) => print_status(&status),
+        ServerMessage::Reloaded(summary) => println!("Reloaded: {summary}"),
+        ServerMessage::InvalidConfig(e) => println!("Failed to reload: {e}"),
+        ServerMessage::NoImagesFound => println!("No images to show"),
+        ServerMessage::Collections { names, current } => {
+            for name in names {
+                let marker = if name == current { '*' } else { ' ' };
+                println!("{marker} {name}");
+            }
+        }
+        ServerMessage::Duplicates { groups, unchecked } => print_duplicates(&groups, unchecked),
+        message => println!("Server: {message:#?}"),
+    }
+}
+
+fn print_status(status: &Status) {
+    let state = if status.running { "running" } else { "stopped" };
+    println!("State: {state}");
+    match &status.active_rule {
+        Some(rule) => println!("Collection: {} (from {rule})", status.collection),
+        None => println!("Collection: {}", status.collection),
+    }
+    for (ii, monitor) in status.monitors.iter().enumerate() {
+        let state = if monitor.running {
+            "running"
+        } else {
+            "stopped"
+        };
+        let image = status
+            .current
+            .get(ii)
+            .map_or("none".into(), |p| p.display().to_string());
+        println!("Monitor {ii} ({}, {state}): {image}", monitor.collection);
+    }
+    if !status.quarantined.is_empty() {
+        println!("Quarantined:");
+        for image in status.quarantined.iter() {
+            println!("  {}: {}", image.path.display(), image.reason);
+        }
+        let more = status
+            .quarantined_count
+            .saturating_sub(status.quarantined.len());
+        if more > 0 {
+            println!("  and {more} more");
+        }
+    }
+}
+
+/// Prints each group of copies with the size of each image, the copy shown by default first
+fn print_duplicates(groups: &[Vec<DuplicateImage>], unchecked: usize) {
+    for (ii, group) in groups.iter().enumerate() {
+        if ii > 0 {
+            println!();
+        }
+        for image in group {
+            let size = image
+                .dimensions
+                .map_or("?".into(), |d| format!("{}x{}", d.width, d.height));
+            println!("  {size:>9} {}", image.path.display());
+        }
+    }
+    if unchecked > 0 {
+        println!("{unchecked} images haven't been checked for copies yet");
+    }
+}
+
+/// Prints the history of each monitor, newest first, marking the image that is currently shown
+fn print_history(monitors: &[MonitorHistory]) {
+    for (monitor, history) in monitors.iter().enumerate() {
+        println!("Monitor {monitor}:");
+        for (ii, image) in history.entries.iter().enumerate().rev() {
+            let marker = if ii == history.position { '*' } else { ' ' };
+            println!("{marker} {ii:>3}: {}", image.display());
+        }
+    }
 }
@@ -0,0 +1,157 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the file in the data directory that bans are saved to
+pub const BANS_FILE: &str = "bans.json";
+
+/// An image that should never be shown
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BannedImage {
+    /// Where the image was when it was banned
+    pub path: PathBuf,
+    /// Size of the file in bytes, used to avoid hashing files that can't match
+    pub size: u64,
+    /// Hash of the file contents so the ban still applies if the file is renamed
+    pub hash: u64,
+}
+
+/// Images that have been banned from being shown
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Bans {
+    bans: Vec<BannedImage>,
+}
+
+impl Bans {
+    /// Loads the bans from the file at `path`, returning no bans if it can't be read
+    pub fn load<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the bans to the file at `path`
+    pub fn save<P>(&self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let data = serde_json::to_string(self).expect("Bans should always be serializable");
+        std::fs::write(path, data)
+    }
+
+    /// Bans the image at `path`
+    pub fn ban(&mut self, path: PathBuf) -> std::io::Result<&BannedImage> {
+        let size = path.metadata()?.len();
+        let hash = content_hash(&path)?;
+        self.bans.retain(|b| b.path != path);
+        self.bans.push(BannedImage { path, size, hash });
+        Ok(self.bans.last().unwrap())
+    }
+
+    /// Removes the ban on `path`, returns false if it wasn't banned
+    pub fn remove(&mut self, path: &Path) -> bool {
+        let len = self.bans.len();
+        self.bans.retain(|b| b.path != path);
+        self.bans.len() != len
+    }
+
+    /// If the image at `path` is banned, either by its path or its contents
+    pub fn is_banned(&self, path: &Path) -> bool {
+        if self.bans.is_empty() {
+            return false;
+        }
+        if self.bans.iter().any(|b| b.path == path) {
+            return true;
+        }
+
+        let Ok(size) = path.metadata().map(|m| m.len()) else {
+            return false;
+        };
+        self.is_banned_with(path, size, || content_hash(path).ok())
+    }
+
+    /// Like `is_banned`, for when the size of the file is already known and its hash may be too.
+    /// `hash` is only called if an image of the same size has been banned.
+    pub fn is_banned_with<F>(&self, path: &Path, size: u64, hash: F) -> bool
+    where
+        F: FnOnce() -> Option<u64>,
+    {
+        if self.bans.iter().any(|b| b.path == path) {
+            return true;
+        }
+        if !self.bans.iter().any(|b| b.size == size) {
+            return false;
+        }
+        let Some(hash) = hash() else {
+            return false;
+        };
+        self.bans.iter().any(|b| b.size == size && b.hash == hash)
+    }
+
+    /// All banned images
+    pub fn list(&self) -> &[BannedImage] {
+        &self.bans
+    }
+}
+
+/// 64 bit FNV-1a hash of the file's contents
+///
+/// This needs to be stable between builds since it is saved to disk, so the standard library's
+/// hasher can't be used
+pub fn content_hash<P>(path: P) -> std::io::Result<u64>
+where
+    P: AsRef<Path>,
+{
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = [0; 8192];
+    let mut hash = OFFSET_BASIS;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for byte in &buf[..n] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban_follows_renames() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let original = dir.join("a.jpg");
+        let renamed = dir.join("b.jpg");
+        let other = dir.join("c.jpg");
+        std::fs::write(&original, b"some image").unwrap();
+        std::fs::write(&other, b"other image").unwrap();
+
+        let mut bans = Bans::default();
+        bans.ban(original.clone()).unwrap();
+        std::fs::rename(&original, &renamed).unwrap();
+
+        assert!(bans.is_banned(&original));
+        assert!(bans.is_banned(&renamed));
+        assert!(!bans.is_banned(&other));
+
+        assert!(bans.remove(&original));
+        assert!(!bans.is_banned(&renamed));
+    }
+}
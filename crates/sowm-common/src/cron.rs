@@ -0,0 +1,296 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+/// How far ahead to look for a matching time before giving up, expressions like `0 0 30 2 *`
+/// never match
+const MAX_SEARCH_DAYS: i64 = 366 * 5;
+
+/// A cron expression with the usual five fields: minute, hour, day of month, month and day of
+/// week
+///
+/// Each field can be `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or a comma separated
+/// list of those. Months and days of the week can also be given by their three letter names. The
+/// shortcuts `@hourly`, `@daily`, `@midnight`, `@weekly`, `@monthly` and `@yearly` are accepted
+/// too. Like cron, if both the day of month and day of week are restricted a time matches if
+/// either of them does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    /// If the minute `time` falls in matches the expression
+    pub fn matches(&self, time: NaiveDateTime) -> bool {
+        self.matches_date(time.date())
+            && bit(self.hours, time.hour())
+            && bit(self.minutes, time.minute())
+    }
+
+    /// The first matching minute strictly after `time`, `None` if it never matches
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date();
+        let mut first_day = true;
+
+        while (date - start.date()).num_days() <= MAX_SEARCH_DAYS {
+            if self.matches_date(date) {
+                let from = if first_day {
+                    start.time()
+                } else {
+                    NaiveTime::MIN
+                };
+                if let Some(t) = self.first_time_from(from) {
+                    return Some(date.and_time(t));
+                }
+            }
+            date = date.succ_opt()?;
+            first_day = false;
+        }
+        None
+    }
+
+    /// The first matching time of day at or after `from`
+    fn first_time_from(&self, from: NaiveTime) -> Option<NaiveTime> {
+        for hour in from.hour()..24 {
+            if !bit(self.hours, hour) {
+                continue;
+            }
+            let first_minute = if hour == from.hour() {
+                from.minute()
+            } else {
+                0
+            };
+            if let Some(minute) = (first_minute..60).find(|m| bit(self.minutes, *m)) {
+                return NaiveTime::from_hms_opt(hour, minute, 0);
+            }
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !bit(self.months, date.month()) {
+            return false;
+        }
+        let day = bit(self.days, date.day());
+        let weekday = bit(self.weekdays, date.weekday().num_days_from_sunday());
+        // Like cron, either one matching is enough only if both are restricted
+        match self.any_day || self.any_weekday {
+            true => day && weekday,
+            false => day || weekday,
+        }
+    }
+}
+
+fn bit(mask: u64, n: u32) -> bool {
+    mask & (1 << n) != 0
+}
+
+/// Parses one field of the expression into a bit mask, also returning if it started with `*`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<(u64, bool), String> {
+    let value = |s: &str| -> Result<u32, String> {
+        if let Some(ii) = names.iter().position(|n| n.eq_ignore_ascii_case(s)) {
+            return Ok(ii as u32 + min);
+        }
+        match s.parse::<u32>() {
+            Ok(v) if (min..=max).contains(&v) => Ok(v),
+            _ => Err(format!("'{s}' should be a number from {min} to {max}")),
+        }
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("Invalid step in '{part}'")),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/15` means every 15 starting at 5
+                None if part.contains('/') => (value(range)?, max),
+                None => {
+                    let v = value(range)?;
+                    (v, v)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("Range '{range}' is backwards"));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok((mask, field.starts_with('*')))
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expanded = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            s => s,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Cron expression '{s}' should have 5 fields"));
+        };
+
+        const MONTHS: [&str; 12] = [
+            "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+        ];
+        const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+        let err = |e: String| format!("Invalid cron expression '{s}': {e}");
+        let (minutes, _) = parse_field(minutes, 0, 59, &[]).map_err(err)?;
+        let (hours, _) = parse_field(hours, 0, 23, &[]).map_err(err)?;
+        let (days, any_day) = parse_field(days, 1, 31, &[]).map_err(err)?;
+        let (months, _) = parse_field(months, 1, 12, &MONTHS).map_err(err)?;
+        let (mut weekdays, any_weekday) = parse_field(weekdays, 0, 7, &WEEKDAYS).map_err(err)?;
+        // Both 0 and 7 are sunday
+        if bit(weekdays, 7) {
+            weekdays |= 1;
+        }
+
+        Ok(Cron {
+            source: s.trim().to_string(),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day,
+            any_weekday,
+        })
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cron> for String {
+    fn from(c: Cron) -> Self {
+        c.source
+    }
+}
+
+impl Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn next(cron: &str, after: &str) -> Option<NaiveDateTime> {
+        cron.parse::<Cron>().unwrap().next_after(time(after))
+    }
+
+    #[test]
+    fn every_hour_on_the_hour() {
+        assert_eq!(
+            next("0 * * * *", "2025-01-01 10:00"),
+            Some(time("2025-01-01 11:00"))
+        );
+        assert_eq!(
+            next("@hourly", "2025-01-01 10:59"),
+            Some(time("2025-01-01 11:00"))
+        );
+        assert_eq!(
+            next("0 * * * *", "2025-12-31 23:30"),
+            Some(time("2026-01-01 00:00"))
+        );
+    }
+
+    #[test]
+    fn weekday_mornings() {
+        // 2025-01-03 is a friday
+        assert_eq!(
+            next("0 9 * * mon-fri", "2025-01-03 08:00"),
+            Some(time("2025-01-03 09:00"))
+        );
+        assert_eq!(
+            next("0 9 * * 1-5", "2025-01-03 09:00"),
+            Some(time("2025-01-06 09:00"))
+        );
+    }
+
+    #[test]
+    fn steps_and_lists() {
+        assert_eq!(
+            next("*/15 * * * *", "2025-01-01 10:01"),
+            Some(time("2025-01-01 10:15"))
+        );
+        assert_eq!(
+            next("5/20 * * * *", "2025-01-01 10:26"),
+            Some(time("2025-01-01 10:45"))
+        );
+        assert_eq!(
+            next("30 8,20 * * *", "2025-01-01 09:00"),
+            Some(time("2025-01-01 20:30"))
+        );
+    }
+
+    #[test]
+    fn day_of_month_or_week() {
+        // The 15th or any sunday, 2025-02-02 is a sunday
+        assert_eq!(
+            next("0 0 15 * 0", "2025-02-01 12:00"),
+            Some(time("2025-02-02 00:00"))
+        );
+        assert_eq!(
+            next("0 0 15 * 7", "2025-02-10 12:00"),
+            Some(time("2025-02-15 00:00"))
+        );
+        // A step over every day still counts as `*`, so both have to match. 2025-01-13 is the
+        // first odd numbered monday.
+        assert_eq!(
+            next("0 0 */2 * mon", "2025-01-01 12:00"),
+            Some(time("2025-01-13 00:00"))
+        );
+        assert_eq!(
+            next("0 0 */2 * *", "2025-01-01 12:00"),
+            Some(time("2025-01-03 00:00"))
+        );
+    }
+
+    #[test]
+    fn impossible_and_invalid_expressions() {
+        assert_eq!(next("0 0 30 2 *", "2025-01-01 00:00"), None);
+        assert!("0 * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("0 5-2 * * *".parse::<Cron>().is_err());
+        assert!("0 0 * foo *".parse::<Cron>().is_err());
+    }
+}
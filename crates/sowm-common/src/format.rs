@@ -0,0 +1,117 @@
+use std::{fmt::Display, fs::File, io::Read, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of bytes at the start of a file needed to tell what format it is in
+pub const MAGIC_LEN: usize = 12;
+
+/// File formats of images that can be shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Format {
+    Jpeg,
+    Png,
+    /// Only the first frame of animated GIFs is shown
+    Gif,
+    Bmp,
+    Tiff,
+    Webp,
+    Avif,
+    Jxl,
+}
+
+impl Format {
+    pub const ALL: [Format; 8] = [
+        Format::Jpeg,
+        Format::Png,
+        Format::Gif,
+        Format::Bmp,
+        Format::Tiff,
+        Format::Webp,
+        Format::Avif,
+        Format::Jxl,
+    ];
+
+    /// File extensions images in this format usually have
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Jpeg => &["jpg", "jpeg"],
+            Format::Png => &["png"],
+            Format::Gif => &["gif"],
+            Format::Bmp => &["bmp"],
+            Format::Tiff => &["tif", "tiff"],
+            Format::Webp => &["webp"],
+            Format::Avif => &["avif"],
+            Format::Jxl => &["jxl"],
+        }
+    }
+
+    /// Works out the format from the first bytes of a file, `None` if it isn't an image format
+    /// that can be shown
+    pub fn from_magic(magic: &[u8]) -> Option<Format> {
+        const JXL_CONTAINER: [u8; 12] = [
+            0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+        ];
+
+        let format = match magic {
+            [0xff, 0xd8, 0xff, ..] => Format::Jpeg,
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Format::Png,
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Format::Gif,
+            [b'B', b'M', ..] => Format::Bmp,
+            [b'I', b'I', b'*', 0, ..] | [b'M', b'M', 0, b'*', ..] => Format::Tiff,
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Format::Webp,
+            [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => Format::Avif,
+            [0xff, 0x0a, ..] => Format::Jxl,
+            _ if magic.starts_with(&JXL_CONTAINER) => Format::Jxl,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Reads the start of the file at `path` to work out its format
+    pub fn detect<P>(path: P) -> std::io::Result<Option<Format>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut magic = Vec::with_capacity(MAGIC_LEN);
+        File::open(path)?
+            .take(MAGIC_LEN as u64)
+            .read_to_end(&mut magic)?;
+        Ok(Format::from_magic(&magic))
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Format::Jpeg => "JPEG",
+            Format::Png => "PNG",
+            Format::Gif => "GIF",
+            Format::Bmp => "BMP",
+            Format::Tiff => "TIFF",
+            Format::Webp => "WebP",
+            Format::Avif => "AVIF",
+            Format::Jxl => "JPEG XL",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_from_magic_bytes() {
+        let magic = |bytes: &[u8]| Format::from_magic(bytes);
+        assert_eq!(magic(&[0xff, 0xd8, 0xff, 0xe0]), Some(Format::Jpeg));
+        assert_eq!(magic(b"GIF89a\x01\x00"), Some(Format::Gif));
+        assert_eq!(magic(b"RIFF\x10\x00\x00\x00WEBPVP8 "), Some(Format::Webp));
+        assert_eq!(magic(b"RIFF\x10\x00\x00\x00WAVEfmt "), None);
+        assert_eq!(magic(b"\x00\x00\x00\x1cftypavif"), Some(Format::Avif));
+        assert_eq!(magic(b"\x00\x00\x00\x1cftypheic"), None);
+        assert_eq!(magic(b"MM\x00*\x00\x00\x00\x08"), Some(Format::Tiff));
+        assert_eq!(magic(&[0xff, 0x0a, 0xfa]), Some(Format::Jxl));
+        assert_eq!(magic(b"<html>"), None);
+        assert_eq!(magic(b""), None);
+    }
+}
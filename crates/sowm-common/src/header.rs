@@ -0,0 +1,376 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::format::{Format, MAGIC_LEN};
+
+/// feh can't load images with a side longer than this
+const MAX_SIDE: u32 = 32767;
+
+/// How much of the end of a file is searched for the end of image marker, some images have data
+/// after it
+const TAIL_LEN: u64 = 4096;
+
+/// How much of the start of an AVIF is searched for the box holding its size
+const AVIF_HEAD_LEN: u64 = 64 * 1024;
+
+/// Size of an image in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What the header of an image says about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub format: Format,
+    /// `None` for formats whose size isn't read
+    pub dimensions: Option<Dimensions>,
+}
+
+/// Reads the header of the image at `path` to check that it can be shown, going by its magic
+/// bytes rather than its extension. Gives the reason if it can't be.
+pub fn check_image<P>(path: P) -> Result<ImageInfo, String>
+where
+    P: AsRef<Path>,
+{
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    (&mut file)
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)
+        .map_err(|e| e.to_string())?;
+    let Some(format) = Format::from_magic(&magic) else {
+        return Err("not an image in a supported format".to_string());
+    };
+    file.rewind().map_err(|e| e.to_string())?;
+
+    let dimensions = match format {
+        Format::Jpeg => Some(jpeg_dimensions(&mut BufReader::new(&mut file))?),
+        Format::Png => Some(png_dimensions(&mut file)?),
+        Format::Gif => Some(gif_dimensions(&mut file)?),
+        Format::Bmp => Some(bmp_dimensions(&mut file)?),
+        Format::Webp => Some(webp_dimensions(&mut file)?),
+        Format::Tiff => Some(tiff_dimensions(&mut file)?),
+        Format::Avif => avif_dimensions(&mut file)?,
+        Format::Jxl => None,
+    };
+
+    if let Some(Dimensions { width, height }) = dimensions {
+        if width == 0 || height == 0 {
+            return Err("image has no pixels".to_string());
+        }
+        if width > MAX_SIDE || height > MAX_SIDE {
+            return Err(format!(
+                "image is {width}x{height}, which is too big to show"
+            ));
+        }
+    }
+    let end_marker: &[u8] = match format {
+        Format::Jpeg => &[0xff, 0xd9],
+        Format::Png => b"IEND",
+        _ => &[],
+    };
+    if !tail_contains(&mut file, end_marker).map_err(|e| e.to_string())? {
+        return Err("image is truncated".to_string());
+    }
+    Ok(ImageInfo { format, dimensions })
+}
+
+/// Reads the first `N` bytes of the file
+fn read_head<const N: usize, R: Read>(reader: &mut R) -> Result<[u8; N], String> {
+    let mut head = [0; N];
+    reader
+        .read_exact(&mut head)
+        .map_err(|_| "image is truncated".to_string())?;
+    Ok(head)
+}
+
+fn u16_le(bytes: &[u8]) -> u32 {
+    u16::from_le_bytes([bytes[0], bytes[1]]) as u32
+}
+
+fn u24_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+fn u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Walks the JPEG markers up to the start of frame, which holds the dimensions
+fn jpeg_dimensions<R: Read>(reader: &mut R) -> Result<Dimensions, String> {
+    let truncated = |_| "image is truncated".to_string();
+    let mut byte = [0; 1];
+    let read_u16 = |reader: &mut R| -> Result<u16, String> {
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).map_err(truncated)?;
+        Ok(u16::from_be_bytes(buf))
+    };
+
+    // Skip the start of image marker
+    read_u16(reader)?;
+    loop {
+        reader.read_exact(&mut byte).map_err(truncated)?;
+        if byte[0] != 0xff {
+            return Err("invalid JPEG marker".to_string());
+        }
+        // Markers can be padded with any number of 0xff bytes
+        while byte[0] == 0xff {
+            reader.read_exact(&mut byte).map_err(truncated)?;
+        }
+        let marker = byte[0];
+        match marker {
+            0x01 | 0xd0..=0xd7 => continue,
+            0xd9 | 0xda => return Err("JPEG has no frame header".to_string()),
+            _ => {}
+        }
+        let len = read_u16(reader)?;
+        if len < 2 {
+            return Err("invalid JPEG segment length".to_string());
+        }
+        // Start of frame markers, apart from the ones that aren't
+        if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            reader.read_exact(&mut byte).map_err(truncated)?;
+            let height = read_u16(reader)? as u32;
+            let width = read_u16(reader)? as u32;
+            return Ok(Dimensions { width, height });
+        }
+        let skipped = std::io::copy(&mut reader.take(len as u64 - 2), &mut std::io::sink())
+            .map_err(|e| e.to_string())?;
+        if skipped != len as u64 - 2 {
+            return Err("image is truncated".to_string());
+        }
+    }
+}
+
+/// Reads the dimensions from the IHDR chunk, which always comes first
+fn png_dimensions<R: Read>(reader: &mut R) -> Result<Dimensions, String> {
+    let header: [u8; 24] = read_head(reader)?;
+    if &header[12..16] != b"IHDR" {
+        return Err("PNG doesn't start with a header chunk".to_string());
+    }
+    let width = u32_be(&header[16..20]);
+    let height = u32_be(&header[20..24]);
+    Ok(Dimensions { width, height })
+}
+
+/// Reads the size of the logical screen, which every frame is drawn on
+fn gif_dimensions<R: Read>(reader: &mut R) -> Result<Dimensions, String> {
+    let header: [u8; 10] = read_head(reader)?;
+    Ok(Dimensions {
+        width: u16_le(&header[6..]),
+        height: u16_le(&header[8..]),
+    })
+}
+
+/// Reads the size from the bitmap header, which is smaller in old OS/2 bitmaps
+fn bmp_dimensions<R: Read>(reader: &mut R) -> Result<Dimensions, String> {
+    let header: [u8; 26] = read_head(reader)?;
+    if u32::from_le_bytes(header[14..18].try_into().unwrap()) == 12 {
+        return Ok(Dimensions {
+            width: u16_le(&header[18..]),
+            height: u16_le(&header[20..]),
+        });
+    }
+    let width = i32::from_le_bytes(header[18..22].try_into().unwrap());
+    // Negative heights are images stored top down
+    let height = i32::from_le_bytes(header[22..26].try_into().unwrap());
+    Ok(Dimensions {
+        width: width.max(0) as u32,
+        height: height.unsigned_abs(),
+    })
+}
+
+/// Reads the size from the first chunk, which depends on how the image is compressed
+fn webp_dimensions<R: Read>(reader: &mut R) -> Result<Dimensions, String> {
+    let header: [u8; 30] = read_head(reader)?;
+    match &header[12..16] {
+        b"VP8 " => Ok(Dimensions {
+            width: u16_le(&header[26..]) & 0x3fff,
+            height: u16_le(&header[28..]) & 0x3fff,
+        }),
+        b"VP8L" => {
+            let bits = u32::from_le_bytes(header[21..25].try_into().unwrap());
+            Ok(Dimensions {
+                width: (bits & 0x3fff) + 1,
+                height: ((bits >> 14) & 0x3fff) + 1,
+            })
+        }
+        b"VP8X" => Ok(Dimensions {
+            width: u24_le(&header[24..]) + 1,
+            height: u24_le(&header[27..]) + 1,
+        }),
+        _ => Err("WebP has an unknown first chunk".to_string()),
+    }
+}
+
+/// Reads the size from the tags of the first image in the file
+fn tiff_dimensions(file: &mut File) -> Result<Dimensions, String> {
+    const WIDTH_TAG: u16 = 256;
+    const HEIGHT_TAG: u16 = 257;
+    const SHORT: u16 = 3;
+
+    let header: [u8; 8] = read_head(file)?;
+    let big_endian = header[0] == b'M';
+    let u16_at = |b: &[u8]| match big_endian {
+        true => u16::from_be_bytes([b[0], b[1]]),
+        false => u16::from_le_bytes([b[0], b[1]]),
+    };
+    let u32_at = |b: &[u8]| match big_endian {
+        true => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        false => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+    };
+
+    file.seek(SeekFrom::Start(u32_at(&header[4..]) as u64))
+        .map_err(|e| e.to_string())?;
+    let count: [u8; 2] = read_head(file)?;
+    let (mut width, mut height) = (None, None);
+    for _ in 0..u16_at(&count) {
+        let entry: [u8; 12] = read_head(file)?;
+        let value = match u16_at(&entry[2..]) {
+            SHORT => u16_at(&entry[8..]) as u32,
+            _ => u32_at(&entry[8..]),
+        };
+        match u16_at(&entry) {
+            WIDTH_TAG => width = Some(value),
+            HEIGHT_TAG => height = Some(value),
+            _ => {}
+        }
+    }
+    match (width, height) {
+        (Some(width), Some(height)) => Ok(Dimensions { width, height }),
+        _ => Err("TIFF has no size".to_string()),
+    }
+}
+
+/// Looks for the image spatial extents property near the start of the file, `None` if it isn't
+/// there
+fn avif_dimensions(file: &mut File) -> Result<Option<Dimensions>, String> {
+    let mut head = Vec::new();
+    file.take(AVIF_HEAD_LEN)
+        .read_to_end(&mut head)
+        .map_err(|e| e.to_string())?;
+    // The box type is followed by its version and flags, then the width and height
+    let Some(ii) = head.windows(4).position(|w| w == b"ispe") else {
+        return Ok(None);
+    };
+    match head.get(ii + 8..ii + 16) {
+        Some(size) => Ok(Some(Dimensions {
+            width: u32_be(size),
+            height: u32_be(&size[4..]),
+        })),
+        None => Err("image is truncated".to_string()),
+    }
+}
+
+/// If `marker` is near the end of the file, an empty marker always is
+fn tail_contains(file: &mut File, marker: &[u8]) -> std::io::Result<bool> {
+    if marker.is_empty() {
+        return Ok(true);
+    }
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    Ok(tail.windows(marker.len()).any(|w| w == marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        data.extend(13u32.to_be_bytes());
+        data.extend(b"IHDR");
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend([8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend([0, 0, 0, 0]);
+        data.extend(b"IEND");
+        data.extend([0xae, 0x42, 0x60, 0x82]);
+        data
+    }
+
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut data = vec![0xff, 0xd8];
+        data.extend([0xff, 0xe0, 0, 6, b'J', b'F', b'I', b'F']);
+        data.extend([0xff, 0xc0, 0, 11, 8]);
+        data.extend(height.to_be_bytes());
+        data.extend(width.to_be_bytes());
+        data.extend([1, 1, 0x11, 0]);
+        data.extend([0xff, 0xda, 0, 2, 0x12, 0x34, 0xff, 0xd9]);
+        data
+    }
+
+    #[test]
+    fn valid_and_broken_images() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let check = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            check_image(&path)
+        };
+
+        let size = |name, data: &[u8]| check(name, data).map(|i| i.dimensions);
+        let dimensions = |width, height| Ok(Some(Dimensions { width, height }));
+        assert_eq!(size("a.png", &png(1920, 1080)), dimensions(1920, 1080));
+        assert_eq!(size("b.jpg", &jpeg(2560, 1440)), dimensions(2560, 1440));
+        // Mislabeled files are fine as long as they are images
+        assert_eq!(
+            check("c.jpg", &png(640, 480)).map(|i| i.format),
+            Ok(Format::Png)
+        );
+
+        let full = jpeg(2560, 1440);
+        assert!(check("truncated.jpg", &full[..full.len() - 4]).is_err());
+        let full = png(640, 480);
+        assert!(check("truncated.png", &full[..30]).is_err());
+        assert!(check("empty.png", &png(0, 480)).is_err());
+        assert!(check("huge.png", &png(40000, 480)).is_err());
+        assert!(check("text.jpg", b"<html>not found</html>").is_err());
+    }
+
+    #[test]
+    fn other_format_sizes() {
+        let gif = b"GIF89a\x80\x07\x38\x04\x00\x00\x00;";
+        assert_eq!(
+            gif_dimensions(&mut &gif[..]),
+            Ok(Dimensions {
+                width: 1920,
+                height: 1080
+            })
+        );
+
+        let mut bmp = b"BM".to_vec();
+        bmp.extend([0; 12]);
+        bmp.extend(40u32.to_le_bytes());
+        bmp.extend(800i32.to_le_bytes());
+        bmp.extend((-600i32).to_le_bytes());
+        assert_eq!(
+            bmp_dimensions(&mut &bmp[..]),
+            Ok(Dimensions {
+                width: 800,
+                height: 600
+            })
+        );
+
+        let mut webp = b"RIFF\x00\x00\x00\x00WEBPVP8X".to_vec();
+        webp.extend([0; 8]);
+        webp.extend([0x7f, 0x07, 0x00, 0x37, 0x04, 0x00]);
+        assert_eq!(
+            webp_dimensions(&mut &webp[..]),
+            Ok(Dimensions {
+                width: 1920,
+                height: 1080
+            })
+        );
+    }
+}
@@ -0,0 +1,621 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, RecvTimeoutError},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam_deque::{Injector, Stealer, Worker};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bans::content_hash,
+    header::{check_image, ImageInfo},
+    scan::ScanDir,
+};
+
+/// Name of the file in the cache directory that the index is saved to
+pub const INDEX_FILE: &str = "index.bin";
+
+/// How often a scan that is taking a while logs how far it has got
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a scan checks if it has been cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// What is known about a file in an image directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedImage {
+    /// Size of the file in bytes
+    pub size: u64,
+    /// When the file was last modified
+    pub modified: SystemTime,
+    /// What the header of the image says, `None` until it has been read
+    pub info: Option<ImageInfo>,
+    /// Hash of the file's contents, `None` until something has needed it
+    pub hash: Option<u64>,
+    /// Hash of what the image looks like, which is close for images that look alike even if
+    /// they are different sizes. `None` until duplicates have been looked for.
+    pub perceptual: Option<u64>,
+}
+
+impl IndexedImage {
+    fn new(metadata: &Metadata) -> Self {
+        IndexedImage {
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            info: None,
+            hash: None,
+            perceptual: None,
+        }
+    }
+
+    /// If the file is still the one this was recorded from
+    fn is_current(&self, metadata: &Metadata) -> bool {
+        self.size == metadata.len() && metadata.modified().is_ok_and(|m| m == self.modified)
+    }
+}
+
+/// The contents of a directory when it was last listed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedDir {
+    /// Modification time of the directory, which changes whenever an entry is added, removed or
+    /// renamed
+    modified: SystemTime,
+    /// Files in the directory by name, including symbolic links to files
+    files: HashMap<OsString, IndexedImage>,
+    /// Names of the subdirectories, and if they are symbolic links
+    dirs: Vec<(OsString, bool)>,
+}
+
+/// Index of the files in the image directories, kept in the cache directory so huge libraries
+/// don't have to be walked on every start
+///
+/// Directories are only listed again if their modification time has changed. Files changed in
+/// place don't change the directory, so what was read from a file is checked against its size
+/// and modification time before it is used.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    /// Directories by path
+    dirs: HashMap<OsString, IndexedDir>,
+    /// If anything has changed since the index was loaded or saved
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Index {
+    /// Loads the index from the file at `path`, returning an empty one if it can't be read
+    pub fn load<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| bitcode::deserialize(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the index to the file at `path` if it has changed
+    pub fn save<P>(&mut self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        if !self.changed {
+            return Ok(());
+        }
+        let path = path.as_ref();
+        let data = bitcode::serialize(self).expect("Index should always be serializable");
+        // The index can be big, so write it elsewhere first to never leave half of it behind
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, data)?;
+        std::fs::rename(&partial, path)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// All the images under `dir` that its options pick up, listing the directories that have
+    /// changed since they were last listed. Gives `None` if `cancel` returns true before the scan
+    /// is done, the directories listed by then are still kept.
+    ///
+    /// Directories are looked in by several threads, and threads that run out of directories take
+    /// them from the others. Scans that take a while log how far they have got.
+    pub fn scan(&mut self, dir: &ScanDir, cancel: &dyn Fn() -> bool) -> Option<Vec<PathBuf>> {
+        if cancel() {
+            return None;
+        }
+        let workers: Vec<Worker<Job>> = (0..scan_threads()).map(|_| Worker::new_lifo()).collect();
+        let walk = Walk {
+            dir,
+            known: &self.dirs,
+            queue: Injector::new(),
+            stealers: workers.iter().map(|w| w.stealer()).collect(),
+            pending: AtomicUsize::new(1),
+            visited: Mutex::new(HashSet::new()),
+            stop: AtomicBool::new(false),
+            wakeups: Mutex::new(0),
+            woken: Condvar::new(),
+            dirs_done: AtomicUsize::new(0),
+            images_found: AtomicUsize::new(0),
+        };
+        walk.queue.push((dir.path.clone(), 0));
+
+        let started = Instant::now();
+        let found: Vec<Found> = std::thread::scope(|s| {
+            // Every thread holds a sender, so the channel disconnects once they are all done
+            let (done_tx, done_rx) = channel::<()>();
+            let handles: Vec<_> = workers
+                .into_iter()
+                .map(|local| {
+                    let done_tx = done_tx.clone();
+                    let walk = &walk;
+                    s.spawn(move || {
+                        let _done = done_tx;
+                        walk.run(local)
+                    })
+                })
+                .collect();
+            drop(done_tx);
+
+            let mut last_report = started;
+            while let Err(RecvTimeoutError::Timeout) = done_rx.recv_timeout(CANCEL_POLL) {
+                if cancel() {
+                    walk.stop.store(true, Ordering::Relaxed);
+                    walk.wake();
+                }
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    println!("Scanning {}: {}", dir.path.display(), walk.progress());
+                    last_report = Instant::now();
+                }
+            }
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("Scan thread panicked"))
+                .collect()
+        });
+        let cancelled = walk.stop.load(Ordering::Relaxed);
+        if started.elapsed() >= PROGRESS_INTERVAL {
+            println!("Scanned {}: {}", dir.path.display(), walk.progress());
+        }
+
+        let mut images = Vec::new();
+        for found in found {
+            for (path, listed) in found.listed {
+                self.insert_listing(path, listed);
+            }
+            for path in found.gone {
+                self.remove_dir(&path);
+            }
+            images.extend(found.images);
+        }
+        (!cancelled).then_some(images)
+    }
+
+    /// Size of the file at `path` when its directory was last listed, `None` if it isn't indexed
+    pub fn size(&self, path: &Path) -> Option<u64> {
+        self.get(path).map(|f| f.size)
+    }
+
+    /// What the index knows about the file at `path`, without checking if the file has changed.
+    /// `None` if it isn't indexed.
+    pub fn get(&self, path: &Path) -> Option<&IndexedImage> {
+        let dir = self.dirs.get(path.parent()?.as_os_str())?;
+        dir.files.get(path.file_name()?)
+    }
+
+    /// Adds the file at `path` that appeared after its directory was listed, if the directory is
+    /// indexed. The directory is still listed again on the next scan.
+    pub fn add_file(&mut self, path: &Path) {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return;
+        };
+        let Ok(metadata) = path.metadata() else {
+            return;
+        };
+        if let Some(dir) = self.dirs.get_mut(parent.as_os_str()) {
+            dir.files
+                .entry(name.to_os_string())
+                .or_insert_with(|| IndexedImage::new(&metadata));
+            self.changed = true;
+        }
+    }
+
+    /// Records what the header of the image at `path` says, if it is indexed
+    pub fn set_info(&mut self, path: &Path, info: ImageInfo) {
+        if let Some(file) = self.current(path) {
+            file.info = Some(info);
+            self.changed = true;
+        }
+    }
+
+    /// Records the hash of the contents of the file at `path`, if it is indexed
+    pub fn set_hash(&mut self, path: &Path, hash: u64) {
+        if let Some(file) = self.current(path) {
+            file.hash = Some(hash);
+            self.changed = true;
+        }
+    }
+
+    /// Records the perceptual hash of the image at `path`, if it is indexed
+    pub fn set_perceptual(&mut self, path: &Path, hash: u64) {
+        if let Some(file) = self.current(path) {
+            file.perceptual = Some(hash);
+            self.changed = true;
+        }
+    }
+
+    /// Checks the header of the image at `path` with `check_image`, only reading it again if the
+    /// file has changed since it was last checked
+    pub fn check(&mut self, path: &Path) -> Result<ImageInfo, String> {
+        if let Some(info) = self.current(path).and_then(|f| f.info) {
+            return Ok(info);
+        }
+        let info = check_image(path)?;
+        if let Some(file) = self.current(path) {
+            file.info = Some(info);
+            self.changed = true;
+        }
+        Ok(info)
+    }
+
+    /// Hash of the contents of the file at `path`, only reading it if the file has changed since
+    /// it was last hashed. `None` if it can't be read.
+    pub fn hash(&mut self, path: &Path) -> Option<u64> {
+        if let Some(hash) = self.current(path).and_then(|f| f.hash) {
+            return Some(hash);
+        }
+        let hash = content_hash(path).ok()?;
+        if let Some(file) = self.current(path) {
+            file.hash = Some(hash);
+            self.changed = true;
+        }
+        Some(hash)
+    }
+
+    /// The entry of the file at `path` if it is indexed, forgetting what was read from it if the
+    /// file has changed
+    fn current(&mut self, path: &Path) -> Option<&mut IndexedImage> {
+        let metadata = path.metadata().ok()?;
+        let dir = self.dirs.get_mut(path.parent()?.as_os_str())?;
+        let file = dir.files.get_mut(path.file_name()?)?;
+        if !file.is_current(&metadata) {
+            *file = IndexedImage::new(&metadata);
+            self.changed = true;
+        }
+        Some(file)
+    }
+
+    /// Puts a new listing of the directory at `path` in the index, forgetting about the
+    /// subdirectories that are gone
+    fn insert_listing(&mut self, path: PathBuf, listed: IndexedDir) {
+        let key = path.into_os_string();
+        let gone: Vec<PathBuf> = self
+            .dirs
+            .get(&key)
+            .map(|old| {
+                old.dirs
+                    .iter()
+                    .filter(|(name, _)| !listed.dirs.iter().any(|(n, _)| n == name))
+                    .map(|(name, _)| Path::new(&key).join(name))
+                    .collect()
+            })
+            .unwrap_or_default();
+        for path in gone {
+            self.remove_dir(&path);
+        }
+        self.dirs.insert(key, listed);
+        self.changed = true;
+    }
+
+    /// Forgets the directory at `path` and everything under it
+    fn remove_dir(&mut self, path: &Path) {
+        let len = self.dirs.len();
+        self.dirs.retain(|p, _| !Path::new(p).starts_with(path));
+        self.changed |= self.dirs.len() != len;
+    }
+}
+
+/// A directory waiting to be looked in, and how deep it is below the image directory
+type Job = (PathBuf, usize);
+
+/// What one thread of a scan found
+#[derive(Default)]
+struct Found {
+    images: Vec<PathBuf>,
+    /// Directories that had changed, with what is in them now
+    listed: Vec<(PathBuf, IndexedDir)>,
+    /// Directories that couldn't be read
+    gone: Vec<PathBuf>,
+}
+
+/// What the threads of a scan share
+struct Walk<'a> {
+    dir: &'a ScanDir,
+    /// The index from before the scan, it is only changed once the scan is done
+    known: &'a HashMap<OsString, IndexedDir>,
+    /// Directories that no thread has taken yet
+    queue: Injector<Job>,
+    /// For taking directories queued by other threads
+    stealers: Vec<Stealer<Job>>,
+    /// Directories that are queued or being looked in, the scan is done when there are none
+    pending: AtomicUsize,
+    /// Real paths of the directories looked in, so symbolic links can't lead round in circles
+    visited: Mutex<HashSet<PathBuf>>,
+    /// Set when the scan is cancelled
+    stop: AtomicBool,
+    /// Counts the times there may have been new directories queued or the scan ended, for threads
+    /// with nothing to do to wait on
+    wakeups: Mutex<u64>,
+    woken: Condvar,
+    dirs_done: AtomicUsize,
+    images_found: AtomicUsize,
+}
+
+impl Walk<'_> {
+    /// Looks in directories until there are none left, taking them from the other threads when
+    /// this one runs out
+    fn run(&self, local: Worker<Job>) -> Found {
+        let mut found = Found::default();
+        while !self.stop.load(Ordering::Relaxed) {
+            // Read before looking for work so a wake up in between isn't missed
+            let seen = *self.wakeups.lock().unwrap();
+            match self.find_job(&local) {
+                Some(job) => {
+                    let subdirs = self.visit(job, &mut found);
+                    let queued = !subdirs.is_empty();
+                    // Subdirectories are counted before their parent is done so the count can't
+                    // reach zero while there is still work
+                    for subdir in subdirs {
+                        self.pending.fetch_add(1, Ordering::SeqCst);
+                        local.push(subdir);
+                    }
+                    let last = self.pending.fetch_sub(1, Ordering::SeqCst) == 1;
+                    if queued || last {
+                        self.wake();
+                    }
+                }
+                None if self.pending.load(Ordering::SeqCst) == 0 => break,
+                // Other threads are still looking in directories that may have subdirectories
+                None => {
+                    let wakeups = self.wakeups.lock().unwrap();
+                    drop(self.woken.wait_while(wakeups, |w| *w == seen).unwrap());
+                }
+            }
+        }
+        found
+    }
+
+    /// Wakes the threads waiting for directories to look in
+    fn wake(&self) {
+        *self.wakeups.lock().unwrap() += 1;
+        self.woken.notify_all();
+    }
+
+    /// The next directory for the thread with the queue `local` to look in
+    fn find_job(&self, local: &Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.queue
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(|s| s.steal()).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        })
+    }
+
+    /// Looks in one directory, listing it again if it has changed, and gives the subdirectories
+    /// to look in next
+    fn visit(&self, (path, depth): Job, found: &mut Found) -> Vec<Job> {
+        let settings = self.dir.options.settings();
+        if settings.follow_symlinks {
+            let real = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if !self.visited.lock().unwrap().insert(real) {
+                return Vec::new();
+            }
+        }
+        let Ok(modified) = path.metadata().and_then(|m| m.modified()) else {
+            found.gone.push(path);
+            return Vec::new();
+        };
+        let old = self.known.get(path.as_os_str());
+        let (images, subdirs) = match old {
+            Some(old) if old.modified == modified => self.contents(&path, depth, old),
+            _ => match list_dir(&path, modified, old) {
+                Ok(listed) => {
+                    let contents = self.contents(&path, depth, &listed);
+                    found.listed.push((path, listed));
+                    contents
+                }
+                Err(_) => {
+                    found.gone.push(path);
+                    return Vec::new();
+                }
+            },
+        };
+        self.dirs_done.fetch_add(1, Ordering::Relaxed);
+        self.images_found.fetch_add(images.len(), Ordering::Relaxed);
+        found.images.extend(images);
+        subdirs
+    }
+
+    /// The images in the directory at `path` and the subdirectories to look in
+    fn contents(
+        &self,
+        path: &Path,
+        depth: usize,
+        listing: &IndexedDir,
+    ) -> (Vec<PathBuf>, Vec<Job>) {
+        let (root, options) = (&self.dir.path, &self.dir.options);
+        let images = listing
+            .files
+            .keys()
+            .map(|name| path.join(name))
+            .filter(|file| options.is_image(root, file))
+            .collect();
+        let settings = options.settings();
+        if settings.max_depth.is_some_and(|max| depth >= max) {
+            return (images, Vec::new());
+        }
+        let subdirs = listing
+            .dirs
+            .iter()
+            .filter(|(_, link)| !link || settings.follow_symlinks)
+            .map(|(name, _)| path.join(name))
+            .filter(|subdir| !options.is_left_out(root, subdir))
+            .map(|subdir| (subdir, depth + 1))
+            .collect();
+        (images, subdirs)
+    }
+
+    /// How far the scan has got
+    fn progress(&self) -> String {
+        format!(
+            "{} directories, {} images",
+            self.dirs_done.load(Ordering::Relaxed),
+            self.images_found.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// Threads to scan with. Listing directories is mostly waiting on the disk or the network, so
+/// there are more threads than cores.
+fn scan_threads() -> usize {
+    std::thread::available_parallelism()
+        .map_or(4, |n| n.get() * 2)
+        .clamp(2, 16)
+}
+
+/// Lists the directory at `path`, keeping what was read from files in `old` that haven't changed
+fn list_dir(
+    path: &Path,
+    modified: SystemTime,
+    old: Option<&IndexedDir>,
+) -> std::io::Result<IndexedDir> {
+    let mut files = HashMap::new();
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(path)?.filter_map(|e| e.ok()) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        // Symbolic links count as whatever they point at
+        let link = file_type.is_symlink();
+        let metadata = match link {
+            true => std::fs::metadata(entry.path()),
+            false => entry.metadata(),
+        };
+        let Ok(metadata) = metadata else {
+            continue;
+        };
+        let name = entry.file_name();
+        if metadata.is_dir() {
+            dirs.push((name, link));
+        } else if metadata.is_file() {
+            let file = match old.and_then(|o| o.files.get(&name)) {
+                Some(file) if file.is_current(&metadata) => file.clone(),
+                _ => IndexedImage::new(&metadata),
+            };
+            files.insert(name, file);
+        }
+    }
+    Ok(IndexedDir {
+        modified,
+        files,
+        dirs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    use crate::scan::{ScanOptions, ScanSettings};
+
+    #[test]
+    fn only_changed_directories_are_listed_again() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("images");
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        for file in ["a.jpg", "nested/b.png", "notes.txt"] {
+            std::fs::write(root.join(file), b"\x89PNG\r\n\x1a\n").unwrap();
+        }
+        let options = ScanOptions::new(&ScanSettings::default(), vec!["jpg".into(), "png".into()]);
+        let dir = ScanDir {
+            path: root.clone(),
+            options: options.unwrap(),
+        };
+        let scan = |index: &mut Index| {
+            let mut images = index.scan(&dir, &|| false).unwrap();
+            images.sort();
+            images
+        };
+
+        let mut index = Index::default();
+        assert_eq!(
+            scan(&mut index),
+            [root.join("a.jpg"), root.join("nested/b.png")]
+        );
+        // Saved outside the directory so saving doesn't change it
+        let saved = temp.path().join("index.bin");
+        index.save(&saved).unwrap();
+        let mut index = Index::load(&saved);
+        assert_eq!(index.size(&root.join("a.jpg")), Some(8));
+        assert!(index.hash(&root.join("a.jpg")).is_some());
+        assert!(index.changed);
+
+        // A listing that is out of date is used as long as the directory hasn't changed
+        let mut listing = index.dirs[root.as_os_str()].clone();
+        listing.files.remove(OsStr::new("a.jpg"));
+        index.dirs.insert(root.as_os_str().into(), listing);
+        assert_eq!(scan(&mut index), [root.join("nested/b.png")]);
+
+        index.dirs.get_mut(root.as_os_str()).unwrap().modified = SystemTime::UNIX_EPOCH;
+        assert_eq!(
+            scan(&mut index),
+            [root.join("a.jpg"), root.join("nested/b.png")]
+        );
+
+        std::fs::remove_dir_all(root.join("nested")).unwrap();
+        assert_eq!(scan(&mut index), [root.join("a.jpg")]);
+        assert!(!index.dirs.contains_key(root.join("nested").as_os_str()));
+    }
+
+    #[test]
+    fn parallel_scan_finds_what_a_walk_does() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let mut files = vec!["top.png".to_string(), ".hidden/a.jpg".to_string()];
+        for a in 0..8 {
+            for b in 0..8 {
+                files.push(format!("{a}/{b}/image.jpg"));
+                files.push(format!("{a}/{b}/notes.txt"));
+            }
+            files.push(format!("{a}/image.png"));
+        }
+        for file in files.iter() {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"data").unwrap();
+        }
+
+        for toml in ["", "skip_hidden = true\nmax_depth = 1"] {
+            let settings: ScanSettings = toml::from_str(toml).unwrap();
+            let dir = ScanDir {
+                path: root.to_path_buf(),
+                options: ScanOptions::new(&settings, vec!["jpg".into(), "png".into()]).unwrap(),
+            };
+            let mut index = Index::default();
+            let mut scanned = index.scan(&dir, &|| false).unwrap();
+            scanned.sort();
+            let mut walked: Vec<PathBuf> = dir.options.walk(root).collect();
+            walked.sort();
+            assert_eq!(scanned, walked);
+            assert_eq!(index.scan(&dir, &|| true), None);
+        }
+    }
+}
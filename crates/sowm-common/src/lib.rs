@@ -1,62 +1,77 @@
 use directories::BaseDirs;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, HashSet},
     path::{Path, PathBuf},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
-use walkdir::WalkDir;
 
+pub mod bans;
+pub mod cron;
+pub mod format;
+pub mod header;
+pub mod index;
 pub mod packet;
+pub mod quarantine;
+pub mod scan;
+pub mod schedule;
 
-/// Contains all relevant information for communication between client and server as well as other
-/// error prone things that need to be discovered
+use bans::{BannedImage, Bans};
+use cron::Cron;
+use format::Format;
+use header::Dimensions;
+use index::{Index, INDEX_FILE};
+use quarantine::QuarantinedImage;
+use scan::{ImageDirs, ScanDir, ScanOptions, ScanSettings};
+use schedule::{DateRule, ScheduleRule, SolarSchedule};
+
+/// Contains what the daemon needs to start, as well as other error prone things that need to be
+/// discovered
 ///
 /// Should only be created via init() method
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct Init {
     /// Path to config toml file
     pub config_path: PathBuf,
     /// Path to socket file
     pub socket_file: PathBuf,
-    /// If the socket file exists at the time of creation of the object. Clients should check that
-    /// this is true and server should check that this is false.
-    pub does_socket_file_exist: bool,
+    /// When the config file was changed before it was read, `None` if that can't be told
+    pub config_modified: Option<SystemTime>,
     /// Current configuration
     pub config: Config,
-    /// All images found in the image directory
-    pub images: Vec<PathBuf>,
+    /// Index of the image directories from the last run, scans carry on from it
+    pub index: Index,
 }
 
 /// Generates a new Init instance
 pub fn init() -> Result<Init, SowmError> {
     let config_directories = BaseDirs::new().ok_or(SowmError::NoHomeDirectory)?;
-    let socket_file = get_socket_directory()?;
-    let does_socket_file_exist = socket_file
-        .try_exists()
-        .map_err(|_| SowmError::NoUserSocketDirectory(socket_file.clone()))?;
+    let socket_file = socket_file()?;
 
     // TODO: Some of the below errors could be recoverable, we should put these in associated
     // methods with results so that it doesn't block the server from starting for exampele
     let config_path = config_path(&config_directories)?;
+    let config_modified = config_modified(&config_path);
     let config_content = std::fs::read_to_string(&config_path)
         .map_err(|_| SowmError::NoConfigDir(config_path.clone()))?;
-    let config: Config =
-        toml::from_str(&config_content).map_err(|e| SowmError::ConfigParseFail(e))?;
+    let config: Config = toml::from_str(&config_content).map_err(SowmError::ConfigParseFail)?;
     config.is_valid()?;
-    let images = get_images(&config.image_dir);
-    if images.is_empty() {
-        return Err(SowmError::NoImagesFound(config.image_dir.clone()));
-    }
+    let index = cache_file(INDEX_FILE).map(Index::load).unwrap_or_default();
 
     Ok(Init {
-        images,
-        socket_file,
-        does_socket_file_exist,
         config_path,
+        socket_file,
+        config_modified,
         config,
+        index,
     })
 }
 
+/// When the file at `path` was last changed, `None` if that can't be told
+pub fn config_modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
 /// Gets the path to the config.toml, it also creates on based on the default if it doesn't exist
 fn config_path(dirs: &BaseDirs) -> Result<PathBuf, SowmError> {
     let mut dir = dirs.config_dir().to_path_buf();
@@ -77,24 +92,65 @@ fn config_path(dirs: &BaseDirs) -> Result<PathBuf, SowmError> {
     Ok(dir)
 }
 
+/// Gets the path to a file in sowm's state directory, creating the directory if it doesn't exist
+///
+/// Uses `$XDG_STATE_HOME/sowm`, falling back to the local data directory on platforms without a
+/// state directory
+pub fn state_file(name: &str) -> Result<PathBuf, SowmError> {
+    let dirs = BaseDirs::new().ok_or(SowmError::NoHomeDirectory)?;
+    let dir = dirs.state_dir().unwrap_or_else(|| dirs.data_local_dir());
+    sowm_file(dir, name, SowmError::NoStateDir)
+}
+
+/// Gets the path to a file in sowm's data directory (`$XDG_DATA_HOME/sowm`), creating the
+/// directory if it doesn't exist
+pub fn data_file(name: &str) -> Result<PathBuf, SowmError> {
+    let dirs = BaseDirs::new().ok_or(SowmError::NoHomeDirectory)?;
+    sowm_file(dirs.data_dir(), name, SowmError::NoDataDir)
+}
+
+/// Gets the path to a file in sowm's cache directory (`$XDG_CACHE_HOME/sowm`), creating the
+/// directory if it doesn't exist
+pub fn cache_file(name: &str) -> Result<PathBuf, SowmError> {
+    let dirs = BaseDirs::new().ok_or(SowmError::NoHomeDirectory)?;
+    sowm_file(dirs.cache_dir(), name, SowmError::NoCacheDir)
+}
+
+/// Gets the path to `name` in the `sowm` subdirectory of `base`, creating the subdirectory if
+/// needed
+fn sowm_file(base: &Path, name: &str, err: fn(PathBuf) -> SowmError) -> Result<PathBuf, SowmError> {
+    let mut dir = base.join("sowm");
+
+    if !matches!(dir.try_exists(), Ok(true)) {
+        std::fs::create_dir_all(&dir).map_err(|_| err(dir.clone()))?;
+    }
+
+    dir.push(name);
+    Ok(dir)
+}
+
 #[derive(Debug)]
 pub enum SowmError {
     NoHomeDirectory,
     NoUserSocketDirectory(PathBuf),
     NoConfigDir(PathBuf),
+    NoStateDir(PathBuf),
+    NoDataDir(PathBuf),
+    NoCacheDir(PathBuf),
     SerializationFailed(bitcode::Error),
     DeserializationFailed(bitcode::Error),
     ConfigParseFail(toml::de::Error),
     NoImagesFound(PathBuf),
+    UnknownCollection(String),
+    InvalidConfig(String),
 }
 
 impl SowmError {
     pub fn client_critical(&self) -> bool {
-        match self {
-            SowmError::NoUserSocketDirectory(_) => true,
-            SowmError::NoHomeDirectory => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            SowmError::NoUserSocketDirectory(_) | SowmError::NoHomeDirectory
+        )
     }
 }
 
@@ -110,35 +166,94 @@ impl std::fmt::Display for SowmError {
                 "User's config directory didn't exist or wasn't writable: {}",
                 path.display()
             ),
+            Self::NoStateDir(path) => format!(
+                "User's state directory didn't exist or wasn't writable: {}",
+                path.display()
+            ),
+            Self::NoDataDir(path) => format!(
+                "User's data directory didn't exist or wasn't writable: {}",
+                path.display()
+            ),
+            Self::NoCacheDir(path) => format!(
+                "User's cache directory didn't exist or wasn't writable: {}",
+                path.display()
+            ),
             Self::SerializationFailed(e) => format!("Serialization error: {e}"),
             Self::DeserializationFailed(e) => format!("Deserialization error: {e}"),
             Self::ConfigParseFail(e) => format!("Failed parsing config.toml : {e}"),
             Self::NoImagesFound(p) => format!("No images found in {}", p.display()),
+            Self::UnknownCollection(name) => format!("No collection called '{name}' in config"),
+            Self::InvalidConfig(e) => format!("Invalid config: {e}"),
         };
 
         write!(f, "{s}")
     }
 }
 
-/// Gets all images in the provided directory, recursively
-fn get_images<P>(dir: P) -> Vec<PathBuf>
+/// Highest rating an image can be given, this is what favourites are rated
+pub const MAX_RATING: u8 = 5;
+
+/// If the path has one of `extensions`, which should be lower case. Files without an extension
+/// are images if they start with the signature of a supported format.
+pub fn is_supported_image<P>(path: P, extensions: &[String]) -> bool
 where
     P: AsRef<Path>,
 {
-    let image_extensions = ["jpeg", "jpg", "png"];
-    let mut images = Vec::new();
+    let path = path.as_ref();
+    match path.extension() {
+        Some(ext) => ext
+            .to_str()
+            .is_some_and(|ext| extensions.contains(&ext.to_ascii_lowercase())),
+        None => matches!(Format::detect(path), Ok(Some(_))),
+    }
+}
+
+/// Gets all images in `dirs` that aren't banned, without duplicates. Only directories that have
+/// changed since they were put in `index` are listed again. Directories that aren't there are
+/// skipped so a share that isn't mounted doesn't stop the others from being used.
+pub fn get_images(dirs: &[ScanDir], bans: &Bans, index: &mut Index) -> Vec<PathBuf> {
+    get_images_until(dirs, bans, index, &|| false).expect("Scans that aren't cancelled finish")
+}
 
-    for file in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-        if let Some(ext) = file.path().extension() {
-            if image_extensions.contains(&ext.to_ascii_lowercase().to_str().unwrap()) {
-                images.push(file.path().to_owned());
+/// Like `get_images`, but gives up once `cancel` returns true, giving `None`
+pub fn get_images_until(
+    dirs: &[ScanDir],
+    bans: &Bans,
+    index: &mut Index,
+    cancel: &dyn Fn() -> bool,
+) -> Option<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut images = Vec::new();
+    for dir in dirs {
+        if !dir.path.is_dir() {
+            eprintln!("Skipping {}, it isn't available", dir.path.display());
+            continue;
+        }
+        // Directories can overlap or link to each other, so images are told apart by where they
+        // are under the real path of their directory
+        let root = std::fs::canonicalize(&dir.path).unwrap_or_else(|_| dir.path.clone());
+        for image in index.scan(dir, cancel)? {
+            let key = match image.strip_prefix(&dir.path) {
+                Ok(relative) => root.join(relative),
+                Err(_) => image.clone(),
+            };
+            if !seen.insert(key) {
+                continue;
+            }
+            let banned = match index.size(&image) {
+                Some(size) => bans.is_banned_with(&image, size, || index.hash(&image)),
+                None => bans.is_banned(&image),
+            };
+            if !banned {
+                images.push(image);
             }
         }
     }
-    images
+    Some(images)
 }
 
-fn get_socket_directory() -> Result<PathBuf, SowmError> {
+/// Gets the path to the socket the daemon listens on
+pub fn socket_file() -> Result<PathBuf, SowmError> {
     let uid = users::get_current_uid();
     let mut p = PathBuf::new();
     p.push("/run");
@@ -154,18 +269,57 @@ fn get_socket_directory() -> Result<PathBuf, SowmError> {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
-    Start,
-    Stop,
-    Next,
-    Update(Init),
+    /// Start cycling one monitor, or all of them if `monitor` is `None`
+    Start { monitor: Option<usize> },
+    /// Stop cycling one monitor, or all of them if `monitor` is `None`
+    Stop { monitor: Option<usize> },
+    /// Go forward in the history, or to a new set of images if we are at the end of it. If
+    /// `monitor` is given only that monitor gets a new image.
+    Next { monitor: Option<usize> },
+    /// Go back to the previous image in the history of one monitor, or of each monitor if
+    /// `monitor` is `None`
+    Previous { monitor: Option<usize> },
+    /// Request the history of shown images
+    History,
+    /// Show a specific image on one monitor, or all of them if `monitor` is `None`. A pinned
+    /// image stays on the monitor when cycling until it is unpinned.
+    Set {
+        monitor: Option<usize>,
+        path: PathBuf,
+        pin: bool,
+    },
+    /// Remove the pin from one monitor, or all of them if `monitor` is `None`
+    Unpin { monitor: Option<usize> },
+    /// Give the image currently shown on `monitor` the highest rating
+    Favourite { monitor: usize },
+    /// Rate the image currently shown on `monitor`, from 1 to `MAX_RATING`
+    Rate { monitor: usize, rating: u8 },
+    /// Remove the rating of the image currently shown on `monitor`
+    Unrate { monitor: usize },
+    /// Never show the image currently shown on `monitor` again, and replace it
+    Ban { monitor: usize },
+    /// Request the list of banned images
+    Bans,
+    /// Allow a banned image to be shown again
+    Unban(PathBuf),
+    /// Request what the daemon is doing
+    Status,
+    /// Request the names of the collections
+    Collections,
+    /// Show images from the named collection until a different schedule rule applies
+    UseCollection(String),
+    /// Read the config and scan the images again
+    Reload,
+    /// Request the groups of images that are copies of each other
+    Duplicates,
 }
 
 impl ClientMessage {
     pub fn serialize(&self) -> Result<Vec<u8>, SowmError> {
-        bitcode::serialize(self).map_err(|e| SowmError::SerializationFailed(e))
+        bitcode::serialize(self).map_err(SowmError::SerializationFailed)
     }
     pub fn deserialize(v: &[u8]) -> Result<Self, SowmError> {
-        bitcode::deserialize(v).map_err(|e| SowmError::DeserializationFailed(e))
+        bitcode::deserialize(v).map_err(SowmError::DeserializationFailed)
     }
 }
 
@@ -175,40 +329,690 @@ pub enum ServerMessage {
     InvalidCommand,
     DirNotFound,
     NoImagesFound,
+    /// There is no earlier set of images to go back to
+    NoHistory,
+    /// The requested file doesn't exist
+    FileNotFound(PathBuf),
+    /// The requested file isn't an image that can be set as the background
+    UnsupportedImage(PathBuf),
+    /// There is no monitor with the requested index
+    InvalidMonitor(usize),
+    /// Ratings have to be from 1 to `MAX_RATING`
+    InvalidRating(u8),
+    /// No image has been shown yet
+    NoCurrentImage,
+    /// The rating of an image was changed, `None` if it was removed
+    Rated {
+        path: PathBuf,
+        rating: Option<u8>,
+    },
+    /// An image was banned
+    Banned(BannedImage),
+    /// The image wasn't banned
+    NotBanned(PathBuf),
+    /// All banned images
+    Bans(Vec<BannedImage>),
+    /// Something went wrong reading or writing a file
+    IoError(String),
+    Status(Status),
+    /// There is no collection with the requested name
+    UnknownCollection(String),
+    /// The config couldn't be loaded, so the old one is still used
+    InvalidConfig(String),
+    /// The config and images were loaded again
+    Reloaded(ReloadSummary),
+    /// Names of all collections and the one images are being shown from
+    Collections {
+        names: Vec<String>,
+        current: String,
+    },
+    /// Images that each monitor has shown
+    History(Vec<MonitorHistory>),
+    /// Groups of images that are copies of each other, the copy shown by default first.
+    /// `unchecked` is the number of images that haven't been hashed yet.
+    Duplicates {
+        groups: Vec<Vec<DuplicateImage>>,
+        unchecked: usize,
+    },
 }
 
 impl ServerMessage {
     pub fn serialize(&self) -> Result<Vec<u8>, SowmError> {
-        bitcode::serialize(self).map_err(|e| SowmError::SerializationFailed(e))
+        bitcode::serialize(self).map_err(SowmError::SerializationFailed)
     }
     pub fn deserialize(v: &[u8]) -> Result<Self, SowmError> {
-        bitcode::deserialize(v).map_err(|e| SowmError::DeserializationFailed(e))
+        bitcode::deserialize(v).map_err(SowmError::DeserializationFailed)
+    }
+}
+
+/// What the daemon is currently doing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// If wallpapers are being cycled on any monitor
+    pub running: bool,
+    /// Collection that images are being shown from
+    pub collection: String,
+    /// Description of the rule that picked the collection, if any
+    pub active_rule: Option<String>,
+    /// Images on each monitor
+    pub current: Vec<PathBuf>,
+    /// What each monitor is doing
+    pub monitors: Vec<MonitorStatus>,
+    /// The most recently quarantined images, at most `MAX_STATUS_QUARANTINED` of them
+    pub quarantined: Vec<QuarantinedImage>,
+    /// How many images failed validation altogether
+    pub quarantined_count: usize,
+}
+
+/// Most quarantined images listed in the status, so the reply stays small however many there are
+pub const MAX_STATUS_QUARANTINED: usize = 20;
+
+/// What changed when the daemon reloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReloadSummary {
+    /// If the config is different to the one that was loaded before
+    pub config_changed: bool,
+    /// Number of images that weren't found before
+    pub images_added: usize,
+    /// Number of images that aren't there any more
+    pub images_removed: usize,
+}
+
+impl std::fmt::Display for ReloadSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let config = match self.config_changed {
+            true => "config changed",
+            false => "config unchanged",
+        };
+        write!(
+            f,
+            "{config}, {} images added, {} images removed",
+            self.images_added, self.images_removed
+        )
     }
 }
 
+/// An image with copies
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DuplicateImage {
+    pub path: PathBuf,
+    /// Size of the image, if it could be read
+    pub dimensions: Option<Dimensions>,
+}
+
+/// What the daemon is doing on one monitor
 #[derive(Debug, Serialize, Deserialize)]
+pub struct MonitorStatus {
+    /// If wallpapers are being cycled on the monitor
+    pub running: bool,
+    /// Collection that images are being shown from
+    pub collection: String,
+}
+
+/// Images that a monitor has shown
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorHistory {
+    /// Images in the order they were shown, oldest first
+    pub entries: Vec<PathBuf>,
+    /// Index of the image that is currently shown
+    pub position: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default = "default_switch_interval_sec")]
     switch_interval_sec: u64,
-    shuffle: bool,
-    image_dir: PathBuf,
+    /// Line interval switches up with the clock, so a 30 minute interval switches on the hour and
+    /// half past
+    #[serde(default)]
+    align_to_clock: bool,
+    /// Cron expression for when to switch, used instead of the interval if given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    switch_cron: Option<Cron>,
+    /// Deprecated in favour of `order`, `shuffle = false` is the same as `order = "name"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shuffle: Option<bool>,
+    #[serde(default)]
+    order: Option<Order>,
+    /// Directory of the images, or a list of directories that can each have their own scan
+    /// settings
+    #[serde(alias = "image_dirs")]
+    image_dir: ImageDirs,
     num_monitors: usize,
+    /// Extra collections of images that can be shown instead of `image_dir`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    collections: BTreeMap<String, Collection>,
+    /// Collections to show depending on where the sun is, used when none of the `schedule` rules
+    /// match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    solar: Option<SolarSchedule>,
+    /// Times of day to show other collections, the first matching rule is used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    schedule: Vec<ScheduleRule>,
+    /// Days of the year to show other collections, the first matching rule is used. These take
+    /// priority over the `schedule` and `solar` rules.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dates: Vec<DateRule>,
+    /// Settings for single monitors, keyed by the monitor's index or output name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    monitors: BTreeMap<String, MonitorConfig>,
+    /// What to show when there are no images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fallback: Option<Fallback>,
+    /// When to check that images can be shown
+    #[serde(default)]
+    validate: Validation,
+    /// File extensions of images, every supported format if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extensions: Option<Vec<String>>,
+    /// Program that sets the background
+    #[serde(default)]
+    backend: Backend,
+    /// Which files in the image directories are picked up
+    #[serde(flatten)]
+    scan: ScanSettings,
+    /// Prefer images that suit the monitor they are shown on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    matching: Option<Matching>,
+    /// How copies of the same image are found
+    #[serde(default)]
+    duplicates: DuplicateSettings,
+}
+
+fn default_switch_interval_sec() -> u64 {
+    60 * 30
+}
+
+/// When wallpapers should be switched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwitchTiming {
+    /// When the interval has passed since the last switch
+    Interval(Duration),
+    /// Every interval counting from local midnight
+    Aligned(Duration),
+    /// Whenever the cron expression matches
+    Cron(Cron),
+}
+
+/// Name of the collection made of the top level `image_dir`
+pub const DEFAULT_COLLECTION: &str = "default";
+
+/// A named set of images
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collection {
+    #[serde(alias = "image_dirs")]
+    pub image_dir: ImageDirs,
+    /// Order to show the images in, instead of the top level `order`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+    /// How often to switch while this collection is shown, instead of the top level timing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub switch_interval_sec: Option<u64>,
+}
+
+/// What to show when there are no images to show
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Fallback {
+    /// A solid colour written as `#rrggbb`
+    Color { color: String },
+    /// An image shown on every monitor
+    Image { image: PathBuf },
+}
+
+impl Fallback {
+    /// Checks that the colour is a `#rrggbb` colour
+    fn is_valid(&self) -> Result<(), SowmError> {
+        match self {
+            Fallback::Color { color } => {
+                let hex = color.strip_prefix('#').unwrap_or_default();
+                match hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    true => Ok(()),
+                    false => Err(SowmError::InvalidConfig(format!(
+                        "fallback colour '{color}' should be written as #rrggbb"
+                    ))),
+                }
+            }
+            Fallback::Image { .. } => Ok(()),
+        }
+    }
+}
+
+/// How closely an image has to suit a monitor to be preferred for it. Images that don't are
+/// only shown when none of the images do.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Matching {
+    /// How far the aspect ratio of an image can be from the monitor's, as a fraction of the
+    /// monitor's
+    #[serde(default = "default_aspect_tolerance")]
+    pub aspect_tolerance: f64,
+    /// Smallest width and height of an image as a fraction of the monitor's, 1 means the image
+    /// has to be at least as big as the monitor
+    #[serde(default)]
+    pub min_scale: f64,
+}
+
+/// How copies of the same image in different sizes are found, so only one of them is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DuplicateSettings {
+    /// Look for copies of images. Off unless turned on, as every image has to be decoded once to
+    /// hash it, which takes a while for a big library. The hashes are kept in the index so later
+    /// starts only hash new and changed images.
+    #[serde(default = "default_find_duplicates")]
+    pub enabled: bool,
+    /// How many of the 64 bits of two images' perceptual hashes can differ for them to still be
+    /// copies, 0 only finds copies that look the same once shrunk
+    #[serde(default = "default_max_distance")]
+    pub max_distance: u32,
+}
+
+/// Perceptual hashes further apart than this are too far apart to tell copies from other images
+pub const MAX_DUPLICATE_DISTANCE: u32 = 16;
+
+fn default_find_duplicates() -> bool {
+    false
+}
+
+fn default_max_distance() -> u32 {
+    4
+}
+
+impl Default for DuplicateSettings {
+    fn default() -> Self {
+        DuplicateSettings {
+            enabled: default_find_duplicates(),
+            max_distance: default_max_distance(),
+        }
+    }
+}
+
+fn default_aspect_tolerance() -> f64 {
+    0.1
+}
+
+impl Matching {
+    /// If an image of size `image` suits a monitor of size `monitor`
+    pub fn fits(&self, image: Dimensions, monitor: Dimensions) -> bool {
+        if image.height == 0 || monitor.height == 0 {
+            return false;
+        }
+        let aspect = image.width as f64 / image.height as f64;
+        let monitor_aspect = monitor.width as f64 / monitor.height as f64;
+        (aspect / monitor_aspect - 1.0).abs() <= self.aspect_tolerance
+            && image.width as f64 >= monitor.width as f64 * self.min_scale
+            && image.height as f64 >= monitor.height as f64 * self.min_scale
+    }
+
+    fn is_valid(&self) -> Result<(), SowmError> {
+        for (name, value) in [
+            ("aspect_tolerance", self.aspect_tolerance),
+            ("min_scale", self.min_scale),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(SowmError::InvalidConfig(format!(
+                    "matching {name} should be a positive number, not {value}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Settings for one monitor
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorConfig {
+    /// Collection to always show on the monitor instead of the one picked by the schedule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    /// How often the monitor switches, instead of the timing of its collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub switch_interval_sec: Option<u64>,
+    /// How images are fitted to the monitor. feh fits every monitor the same way, so monitors
+    /// that set this all have to set the same fit, which is then used for every monitor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fit: Option<Fit>,
+}
+
+/// How an image is fitted to a monitor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fit {
+    /// Zoom until the monitor is covered, cutting off the edges of the image
+    #[default]
+    Fill,
+    /// Zoom until the image fits on the monitor, leaving borders
+    Max,
+    /// Stretch the image to the size of the monitor
+    Scale,
+    /// Show the image at its size in the middle of the monitor
+    Center,
+    /// Repeat the image across the monitor
+    Tile,
+}
+
+impl std::fmt::Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Fit::Fill => "fill",
+            Fit::Max => "max",
+            Fit::Scale => "scale",
+            Fit::Center => "center",
+            Fit::Tile => "tile",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Program used to set the background
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    #[default]
+    Feh,
+}
+
+impl Backend {
+    /// If the backend can show images in `format`, the others have to be converted first
+    pub fn displays(self, format: Format) -> bool {
+        match self {
+            // imlib2 needs optional loaders for these that usually aren't installed
+            Backend::Feh => !matches!(format, Format::Avif | Format::Jxl),
+        }
+    }
+}
+
+/// When images are checked to make sure they can be shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Validation {
+    /// Never, images are only picked by their extension
+    Off,
+    /// Just before an image is shown
+    #[default]
+    Lazy,
+    /// When the images are scanned, which is slower for large collections
+    Scan,
+}
+
+/// The order images are shown in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// Every image once per pass, in a random order
+    #[default]
+    Shuffle,
+    /// Sorted by path
+    Name,
+    /// Sorted by path, treating runs of digits as numbers so `2.jpg` comes before `10.jpg`
+    Natural,
+    /// Sorted by modification time, newest first
+    Mtime,
+    /// A random image every time, so some may repeat before others are shown
+    Random,
+    /// Like `random`, but images are picked in proportion to their rating
+    Weighted,
 }
 
 impl Config {
     /// If the config is valid or not
     pub fn is_valid(&self) -> Result<(), SowmError> {
-        if !matches!(self.image_dir.try_exists(), Ok(true)) {
-            return Err(SowmError::NoImagesFound(self.image_dir.clone()));
+        // Directories that aren't there are only skipped as they may be a share that isn't
+        // mounted yet
+        if self.collections.contains_key(DEFAULT_COLLECTION) {
+            return Err(SowmError::InvalidConfig(format!(
+                "[collections.{DEFAULT_COLLECTION}] can't be set, the {DEFAULT_COLLECTION} \
+                 collection uses image_dir, order and the switch settings at the top of the config"
+            )));
+        }
+        for rule in self.schedule.iter() {
+            if !self.has_collection(&rule.collection) {
+                return Err(SowmError::UnknownCollection(rule.collection.clone()));
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            fallback.is_valid()?;
+        }
+        if let Some(matching) = &self.matching {
+            matching.is_valid()?;
+        }
+        if self.duplicates.max_distance > MAX_DUPLICATE_DISTANCE {
+            return Err(SowmError::InvalidConfig(format!(
+                "duplicates max_distance can be at most {MAX_DUPLICATE_DISTANCE}, not {}",
+                self.duplicates.max_distance
+            )));
+        }
+        for name in self.collection_names() {
+            for (_, settings) in self
+                .image_dirs(name)
+                .into_iter()
+                .flat_map(|d| d.settings(&self.scan))
+            {
+                ScanOptions::new(&settings, self.extensions()).map_err(SowmError::InvalidConfig)?;
+            }
+        }
+        for (key, monitor) in self.monitors.iter() {
+            if key.parse::<usize>().is_ok_and(|ii| ii >= self.num_monitors) {
+                return Err(SowmError::InvalidConfig(format!(
+                    "there is no monitor {key}, there are only {} monitors",
+                    self.num_monitors
+                )));
+            }
+            if let Some(name) = &monitor.collection {
+                if !self.has_collection(name) {
+                    return Err(SowmError::UnknownCollection(name.clone()));
+                }
+            }
+            if let Some(fit) = monitor.fit.filter(|f| *f != self.fit()) {
+                return Err(SowmError::InvalidConfig(format!(
+                    "monitor {key} fits images with {fit} but another monitor uses {}, feh fits \
+                     every monitor the same way",
+                    self.fit()
+                )));
+            }
+        }
+        for rule in self.dates.iter() {
+            rule.is_valid().map_err(SowmError::InvalidConfig)?;
+            if !self.has_collection(&rule.collection) {
+                return Err(SowmError::UnknownCollection(rule.collection.clone()));
+            }
+        }
+        if let Some(solar) = &self.solar {
+            if !solar.is_valid() {
+                return Err(SowmError::InvalidConfig(format!(
+                    "latitude {} and longitude {} aren't a valid location",
+                    solar.latitude, solar.longitude
+                )));
+            }
+            for name in solar.collections() {
+                if !self.has_collection(name) {
+                    return Err(SowmError::UnknownCollection(name.to_string()));
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// If there is a collection called `name`
+    pub fn has_collection(&self, name: &str) -> bool {
+        self.image_dirs(name).is_some()
+    }
+
+    fn image_dirs(&self, name: &str) -> Option<&ImageDirs> {
+        match name {
+            DEFAULT_COLLECTION => Some(&self.image_dir),
+            name => self.collections.get(name).map(|c| &c.image_dir),
+        }
+    }
+
+    /// Directories of the images in the collection called `name` and how to scan each of them,
+    /// `None` if there is no such collection
+    pub fn collection_dirs(&self, name: &str) -> Option<Vec<ScanDir>> {
+        let dirs = self.image_dirs(name)?.settings(&self.scan);
+        let dirs = dirs
+            .into_iter()
+            .map(|(path, settings)| ScanDir {
+                path: path.to_path_buf(),
+                options: ScanOptions::new(&settings, self.extensions())
+                    .expect("Globs should have been checked when the config was loaded"),
+            })
+            .collect();
+        Some(dirs)
+    }
+
+    /// Names of all the collections, starting with the default one
+    pub fn collection_names(&self) -> Vec<&str> {
+        std::iter::once(DEFAULT_COLLECTION)
+            .chain(self.collections.keys().map(|k| k.as_str()))
+            .collect()
+    }
+
+    /// The order images in the collection called `name` should be shown in
+    pub fn collection_order(&self, name: &str) -> Order {
+        self.collections
+            .get(name)
+            .and_then(|c| c.order)
+            .unwrap_or_else(|| self.order())
+    }
+
+    /// Gets when wallpapers should be switched while the collection called `name` is shown
+    pub fn collection_switch_timing(&self, name: &str) -> SwitchTiming {
+        match self
+            .collections
+            .get(name)
+            .and_then(|c| c.switch_interval_sec)
+        {
+            Some(secs) => self.interval_timing(secs),
+            None => self.switch_timing(),
+        }
+    }
+
+    /// What to show when there are no images
+    pub fn fallback(&self) -> Option<&Fallback> {
+        self.fallback.as_ref()
+    }
+
+    /// When images should be checked
+    pub fn validation(&self) -> Validation {
+        self.validate
+    }
+
+    /// Lower case file extensions of images, without the leading dot
+    pub fn extensions(&self) -> Vec<String> {
+        match &self.extensions {
+            Some(extensions) => extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            None => Format::ALL
+                .iter()
+                .flat_map(|f| f.extensions())
+                .map(|e| e.to_string())
+                .collect(),
+        }
+    }
+
+    /// Program that sets the background
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// How images are matched to monitors, `None` if they aren't
+    pub fn matching(&self) -> Option<Matching> {
+        self.matching
+    }
+
+    /// How copies of the same image are found
+    pub fn duplicates(&self) -> DuplicateSettings {
+        self.duplicates
+    }
+
+    /// Settings for the monitor at `index`, looked up by index first and then by its output name
+    pub fn monitor(&self, index: usize, output: Option<&str>) -> Option<&MonitorConfig> {
+        self.monitors
+            .get(&index.to_string())
+            .or_else(|| self.monitors.get(output?))
+    }
+
+    /// How images are fitted to every monitor, the fit set on the monitors if any of them set one
+    pub fn fit(&self) -> Fit {
+        self.monitors
+            .values()
+            .find_map(|m| m.fit)
+            .unwrap_or_default()
+    }
+
+    /// If any monitor settings are keyed by output name rather than index
+    pub fn has_monitor_outputs(&self) -> bool {
+        self.monitors.keys().any(|k| k.parse::<usize>().is_err())
+    }
+
+    /// Gets when the monitor at `index` should switch, `collection` is the collection being shown
+    /// on monitors that don't have their own
+    pub fn monitor_switch_timing(
+        &self,
+        index: usize,
+        output: Option<&str>,
+        collection: &str,
+    ) -> SwitchTiming {
+        let monitor = self.monitor(index, output);
+        if let Some(secs) = monitor.and_then(|m| m.switch_interval_sec) {
+            return self.interval_timing(secs);
+        }
+        let collection = monitor
+            .and_then(|m| m.collection.as_deref())
+            .unwrap_or(collection);
+        self.collection_switch_timing(collection)
+    }
+
+    /// Timing for switching every `secs` seconds, aligned to the clock if configured
+    fn interval_timing(&self, secs: u64) -> SwitchTiming {
+        let interval = Duration::from_secs(secs);
+        if self.align_to_clock {
+            SwitchTiming::Aligned(interval)
+        } else {
+            SwitchTiming::Interval(interval)
+        }
+    }
+
+    /// Rules for which collection to show at what time of day
+    pub fn schedule(&self) -> &[ScheduleRule] {
+        &self.schedule
+    }
+
+    /// Which collections to show depending on where the sun is
+    pub fn solar(&self) -> Option<&SolarSchedule> {
+        self.solar.as_ref()
+    }
+
+    /// Gets the rules for showing collections on certain days
+    pub fn dates(&self) -> &[DateRule] {
+        &self.dates
+    }
+
     /// Gets the interval that wallpapers should be switched
     pub fn switch_interval(&self) -> Duration {
         Duration::from_secs(self.switch_interval_sec)
     }
 
+    /// Gets when wallpapers should be switched
+    pub fn switch_timing(&self) -> SwitchTiming {
+        match (&self.switch_cron, self.align_to_clock) {
+            (Some(cron), _) => SwitchTiming::Cron(cron.clone()),
+            (None, true) => SwitchTiming::Aligned(self.switch_interval()),
+            (None, false) => SwitchTiming::Interval(self.switch_interval()),
+        }
+    }
+
+    /// The order images should be shown in
+    pub fn order(&self) -> Order {
+        match (self.order, self.shuffle) {
+            (Some(order), _) => order,
+            (None, Some(false)) => Order::Name,
+            (None, _) => Order::Shuffle,
+        }
+    }
+
     /// Number of monitors in the config file
     pub fn num_monitors(&self) -> usize {
         self.num_monitors
@@ -218,10 +1022,25 @@ impl Config {
 impl Default for Config {
     fn default() -> Self {
         Config {
-            switch_interval_sec: 60 * 30,
-            shuffle: true,
-            image_dir: ".".into(),
+            switch_interval_sec: default_switch_interval_sec(),
+            align_to_clock: false,
+            switch_cron: None,
+            shuffle: None,
+            order: Some(Order::Shuffle),
+            image_dir: ImageDirs::One(".".into()),
             num_monitors: 1,
+            collections: BTreeMap::new(),
+            solar: None,
+            schedule: Vec::new(),
+            dates: Vec::new(),
+            monitors: BTreeMap::new(),
+            fallback: None,
+            validate: Validation::default(),
+            extensions: None,
+            backend: Backend::default(),
+            scan: ScanSettings::default(),
+            matching: None,
+            duplicates: DuplicateSettings::default(),
         }
     }
 }
@@ -239,8 +1058,268 @@ mod tests {
 
     #[test]
     fn pipe_dir_exists() {
-        let mut path = get_socket_directory().expect("Couldn't get socket directory");
+        let mut path = socket_file().expect("Couldn't get socket directory");
         path.pop();
         assert!(matches!(path.try_exists(), Ok(true)));
     }
+
+    #[test]
+    fn legacy_shuffle_flag() {
+        let c: Config = toml::from_str(
+            "switch_interval_sec = 1\nshuffle = false\nimage_dir = \".\"\nnum_monitors = 1",
+        )
+        .unwrap();
+        assert_eq!(c.order(), Order::Name);
+
+        let c: Config =
+            toml::from_str("switch_interval_sec = 1\nshuffle = false\norder = \"mtime\"\nimage_dir = \".\"\nnum_monitors = 1")
+                .unwrap();
+        assert_eq!(c.order(), Order::Mtime);
+    }
+
+    #[test]
+    fn schedule_needs_known_collection() {
+        let mut c = Config::default();
+        c.schedule.push(ScheduleRule {
+            from: "18:00".parse().unwrap(),
+            to: "07:00".parse().unwrap(),
+            collection: "night".into(),
+        });
+        assert!(c.is_valid().is_err());
+
+        c.collections.insert(
+            "night".into(),
+            Collection {
+                image_dir: ImageDirs::One(".".into()),
+                order: None,
+                switch_interval_sec: None,
+            },
+        );
+        assert!(c.is_valid().is_ok());
+        let str = toml::to_string_pretty(&c).expect("Failed to seralize config to toml");
+        let parsed: Config = toml::from_str(&str).expect("Failed to parse seralized config");
+        assert_eq!(parsed.schedule(), c.schedule());
+    }
+
+    #[test]
+    fn collection_overrides() {
+        let toml = "switch_cron = \"@hourly\"\norder = \"name\"\nimage_dir = \".\"\nnum_monitors = 1\n\
+                    [collections.work]\nimage_dir = \".\"\norder = \"mtime\"\nswitch_interval_sec = 60\n\
+                    [collections.games]\nimage_dir = \".\"";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert_eq!(c.collection_names(), ["default", "games", "work"]);
+        assert_eq!(c.collection_order("work"), Order::Mtime);
+        assert_eq!(c.collection_order("games"), Order::Name);
+        assert_eq!(
+            c.collection_switch_timing("work"),
+            SwitchTiming::Interval(Duration::from_secs(60))
+        );
+        assert!(matches!(
+            c.collection_switch_timing(DEFAULT_COLLECTION),
+            SwitchTiming::Cron(_)
+        ));
+
+        // The default collection is set at the top of the config
+        let toml =
+            "image_dir = \".\"\nnum_monitors = 1\n[collections.default]\nimage_dir = \"/tmp\"";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn monitor_settings() {
+        let toml = "image_dir = \".\"\nnum_monitors = 2\n\
+                    [collections.vertical]\nimage_dir = \".\"\nswitch_interval_sec = 120\n\
+                    [monitors.1]\ncollection = \"vertical\"\nfit = \"max\"\n\
+                    [monitors.DP-1]\nswitch_interval_sec = 3600";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert!(c.is_valid().is_ok());
+        assert!(c.has_monitor_outputs());
+        assert_eq!(c.monitor(1, Some("DP-1")).unwrap().fit, Some(Fit::Max));
+        assert_eq!(c.fit(), Fit::Max);
+        assert_eq!(
+            c.monitor_switch_timing(0, Some("DP-1"), DEFAULT_COLLECTION),
+            SwitchTiming::Interval(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            c.monitor_switch_timing(1, None, DEFAULT_COLLECTION),
+            SwitchTiming::Interval(Duration::from_secs(120))
+        );
+        assert_eq!(
+            c.monitor_switch_timing(0, None, DEFAULT_COLLECTION),
+            c.switch_timing()
+        );
+
+        let c: Config = toml::from_str(&toml.replace("monitors.1", "monitors.2")).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+
+        // feh can't fit the monitors differently
+        let mismatched = toml.replace("3600", "3600\nfit = \"tile\"");
+        let c: Config = toml::from_str(&mismatched).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+        let matched = toml.replace("3600", "3600\nfit = \"max\"");
+        let c: Config = toml::from_str(&matched).unwrap();
+        assert!(c.is_valid().is_ok());
+    }
+
+    #[test]
+    fn fallback_colour_or_image() {
+        let toml = "image_dir = \".\"\nnum_monitors = 1\n[fallback]\ncolor = \"#1e1e2e\"";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert!(c.is_valid().is_ok());
+        assert!(matches!(c.fallback(), Some(Fallback::Color { .. })));
+
+        let c: Config = toml::from_str(&toml.replace("#1e1e2e", "blue")).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+
+        let c: Config = toml::from_str(&toml.replace("color", "image")).unwrap();
+        assert!(matches!(c.fallback(), Some(Fallback::Image { .. })));
+    }
+
+    #[test]
+    fn images_matched_to_monitors() {
+        let toml = "image_dir = \".\"\nnum_monitors = 1\n[matching]\nmin_scale = 1.0";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert!(c.is_valid().is_ok());
+        let matching = c.matching().unwrap();
+        let size = |width, height| Dimensions { width, height };
+        let monitor = size(2560, 1440);
+        assert!(matching.fits(size(3840, 2160), monitor));
+        assert!(matching.fits(size(2560, 1600), monitor));
+        assert!(!matching.fits(size(1920, 1080), monitor));
+        assert!(!matching.fits(size(3000, 4000), monitor));
+        assert!(!matching.fits(size(5120, 1440), monitor));
+
+        let c: Config = toml::from_str(&toml.replace("1.0", "-1.0")).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn duplicate_settings() {
+        let toml = "image_dir = \".\"\nnum_monitors = 1";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert_eq!(c.duplicates(), DuplicateSettings::default());
+        assert!(!c.duplicates().enabled);
+
+        // The generated config shows how to turn it on
+        let generated = toml::to_string_pretty(&Config::default()).unwrap();
+        assert!(generated.contains("[duplicates]\nenabled = false\n"));
+
+        let toml = format!("{toml}\n[duplicates]\nmax_distance = 40");
+        let c: Config = toml::from_str(&toml).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn date_rules_in_config() {
+        let toml = "image_dir = \".\"\nnum_monitors = 1\n\
+                    [[dates]]\nfrom = \"12-01\"\nto = \"02-28\"\ncollection = \"default\"\n\
+                    [[dates]]\non = \"12-25\"\ncollection = \"christmas\"";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert_eq!(c.dates().len(), 2);
+        assert!(matches!(c.is_valid(), Err(SowmError::UnknownCollection(_))));
+
+        let c: Config = toml::from_str(&toml.replace("christmas", "default")).unwrap();
+        assert!(c.is_valid().is_ok());
+        let parsed: Config = toml::from_str(&toml::to_string_pretty(&c).unwrap()).unwrap();
+        assert_eq!(parsed.dates(), c.dates());
+    }
+
+    #[test]
+    fn solar_needs_valid_location() {
+        let toml = "switch_interval_sec = 1\nimage_dir = \".\"\nnum_monitors = 1\n\
+                    [solar]\nlatitude = 95.0\nlongitude = 0.0\n\
+                    day = \"default\"\ndusk = \"default\"\nnight = \"default\"";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+
+        let c: Config = toml::from_str(&toml.replace("95.0", "51.5")).unwrap();
+        assert!(c.is_valid().is_ok());
+    }
+
+    #[test]
+    fn cron_overrides_interval() {
+        let c: Config = toml::from_str(
+            "switch_cron = \"0 9 * * mon-fri\"\nimage_dir = \".\"\nnum_monitors = 1",
+        )
+        .unwrap();
+        assert!(matches!(c.switch_timing(), SwitchTiming::Cron(_)));
+
+        let c: Config = toml::from_str(
+            "switch_interval_sec = 60\nalign_to_clock = true\nimage_dir = \".\"\nnum_monitors = 1",
+        )
+        .unwrap();
+        assert_eq!(
+            c.switch_timing(),
+            SwitchTiming::Aligned(Duration::from_secs(60))
+        );
+
+        let c = "switch_cron = \"0 25 * * *\"\nimage_dir = \".\"\nnum_monitors = 1";
+        assert!(toml::from_str::<Config>(c).is_err());
+    }
+
+    #[test]
+    fn multiple_image_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        for file in ["a.jpg", "nested/b.jpg"] {
+            std::fs::write(root.join(file), b"data").unwrap();
+        }
+        std::os::unix::fs::symlink(root.join("nested"), root.join("link")).unwrap();
+        let toml = format!(
+            "image_dirs = [\"{0}\", \"{0}/nested\", \"{0}/link\", \
+             {{ path = \"{0}/missing\", max_depth = 0 }}]\n\
+             num_monitors = 1",
+            root.display()
+        );
+        let c: Config = toml::from_str(&toml).unwrap();
+        assert!(c.is_valid().is_ok());
+        let dirs = c.collection_dirs(DEFAULT_COLLECTION).unwrap();
+        assert_eq!(dirs.len(), 4);
+
+        // Overlapping and linked directories give each image once and the missing one is
+        // skipped
+        let mut images = get_images(&dirs, &Bans::default(), &mut Index::default());
+        images.sort();
+        assert_eq!(images, [root.join("a.jpg"), root.join("nested/b.jpg")]);
+    }
+
+    #[test]
+    fn scan_settings() {
+        let toml = "image_dir = \".\"\nnum_monitors = 1\nmax_depth = 2\nexclude = [\"**/@eaDir\"]";
+        let c: Config = toml::from_str(toml).unwrap();
+        assert!(c.is_valid().is_ok());
+        assert_eq!(c.scan.max_depth, Some(2));
+        assert_eq!(c.scan.exclude, ["**/@eaDir"]);
+
+        let c: Config = toml::from_str(&toml.replace("**/@eaDir", "[")).unwrap();
+        assert!(matches!(c.is_valid(), Err(SowmError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn supported_image_extensions() {
+        let extensions = Config::default().extensions();
+        assert!(is_supported_image("a/b.jpg", &extensions));
+        assert!(is_supported_image("a/b.JPEG", &extensions));
+        assert!(is_supported_image("b.Png", &extensions));
+        assert!(is_supported_image("b.webp", &extensions));
+        assert!(!is_supported_image("b.txt", &extensions));
+        assert!(!is_supported_image("png", &extensions));
+
+        let c: Config =
+            toml::from_str("image_dir = \".\"\nnum_monitors = 1\nextensions = [\".JPG\", \"jxl\"]")
+                .unwrap();
+        let extensions = c.extensions();
+        assert_eq!(extensions, ["jpg", "jxl"]);
+        assert!(!is_supported_image("b.png", &extensions));
+
+        // Files without an extension are recognised by their contents
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("image");
+        std::fs::write(&path, b"GIF89a\x01\x00\x01\x00").unwrap();
+        assert!(is_supported_image(&path, &extensions));
+        std::fs::write(&path, b"plain text").unwrap();
+        assert!(!is_supported_image(&path, &extensions));
+    }
 }
@@ -1,4 +1,8 @@
-const PACKET_VERSION: [u8; 4] = ['v' as u8, '0' as u8, '.' as u8, '1' as u8];
+const PACKET_VERSION: [u8; 4] = [b'v', b'0', b'.', b'2'];
+
+/// Longest data section that will be read from a packet. Anything longer is rejected before a
+/// buffer is allocated for it.
+pub const MAX_PACKET_LEN: usize = 8 << 20;
 
 /// A simple packet to be sent over a socket.
 ///
@@ -7,17 +11,15 @@ const PACKET_VERSION: [u8; 4] = ['v' as u8, '0' as u8, '.' as u8, '1' as u8];
 /// The header is 8 bytes defined as the following:
 ///
 ///   0   1   2   3   4   5   6   7
-///  'v' '0' '.' '1'  L   H   R   R
-///
-///  Where bytes 0-3 are version string 'v1.0' in ascii.
+///  'v' '0' '.' '2'  L0  L1  L2  L3
 ///
-///  Bytes 4 and 5 contain the low and high bytes of a u16 integer describing the length of the
-///  data part of the packet, assumed to be in little endian. N = L | (H << 8)
+///  Where bytes 0-3 are version string 'v0.2' in ascii.
 ///
-///  Bytes 7 and 8 are currently reserved and should be set to 0.
+///  Bytes 4 to 7 contain a u32 integer describing the length of the data part of the packet, in
+///  little endian. N = L0 | (L1 << 8) | (L2 << 16) | (L3 << 24)
 ///
 ///  The remainder of the packet is the data, which should be exactly equal in length defined in
-///  the header
+///  the header, and no longer than `MAX_PACKET_LEN`
 pub struct Packet {
     header: [u8; 8],
     data: Vec<u8>,
@@ -31,25 +33,22 @@ impl Packet {
     }
 
     pub fn new(data: Vec<u8>) -> Self {
-        if data.len() > u16::MAX as usize {}
-
         // TODO: Make achievement for exceeding this limit
-        let len: u16 = data
+        let len: u32 = data
             .len()
             .try_into()
-            .expect("Data is too much to be stored as u16");
+            .expect("Data is too much to be stored as u32");
 
-        // TODO: Make achievement for running on big endian system
-        // Assumes little endian
+        let len = len.to_le_bytes();
         let header = [
             PACKET_VERSION[0],
             PACKET_VERSION[1],
             PACKET_VERSION[2],
             PACKET_VERSION[3],
-            (len & 0x00FF) as u8,
-            ((len & 0xFF00) >> 8) as u8,
-            0,
-            0,
+            len[0],
+            len[1],
+            len[2],
+            len[3],
         ];
 
         Packet { header, data }
@@ -73,25 +72,36 @@ impl Packet {
             return Err(PacketError::BadVersion);
         }
 
-        if header[6] != 0 || header[7] != 0 {
-            return Err(PacketError::ReservedNotZero);
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if len > MAX_PACKET_LEN {
+            return Err(PacketError::TooLong(len));
         }
-
-        let len_low = header[4] as usize;
-        let len_high = header[5] as usize;
-        Ok(len_low | len_high << 8)
+        Ok(len)
     }
 }
 
 #[derive(Debug)]
 pub enum PacketError {
     BadVersion,
-    ReservedNotZero,
+    TooLong(usize),
+}
+
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadVersion => write!(f, "Packet has an unknown version"),
+            Self::TooLong(len) => write!(
+                f,
+                "Packet of {len} bytes is longer than the limit of {MAX_PACKET_LEN} bytes"
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{bans::BannedImage, DuplicateImage, ServerMessage};
 
     #[test]
     fn packet_lengths() {
@@ -115,12 +125,64 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
-    fn bad_packet_lengths() {
-        let len = 10000000;
-        let data = vec![0; len];
-        let packet = Packet::new(data);
-        let len_after = Packet::len_from_header(&packet.header).expect("Header wasn't valid");
-        assert_eq!(len, len_after);
+    fn overlong_packets_are_rejected() {
+        let mut header = [b'v', b'0', b'.', b'2', 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(
+            Packet::len_from_header(&header),
+            Err(PacketError::TooLong(0xffff_ffff))
+        ));
+
+        header[4..].copy_from_slice(&(MAX_PACKET_LEN as u32).to_le_bytes());
+        assert_eq!(Packet::len_from_header(&header).unwrap(), MAX_PACKET_LEN);
+
+        header[0] = b'x';
+        assert!(matches!(
+            Packet::len_from_header(&header),
+            Err(PacketError::BadVersion)
+        ));
+    }
+
+    #[test]
+    fn large_replies_round_trip() {
+        let image = |ii: usize| DuplicateImage {
+            path: format!("/mnt/nas/wallpapers/some/deeply/nested/folder/image-{ii:06}.jpg").into(),
+            dimensions: None,
+        };
+        let groups: Vec<Vec<DuplicateImage>> = (0..2000)
+            .map(|ii| vec![image(2 * ii), image(2 * ii + 1)])
+            .collect();
+        let message = ServerMessage::Duplicates {
+            groups: groups.clone(),
+            unchecked: 0,
+        };
+        match round_trip(message) {
+            ServerMessage::Duplicates {
+                groups: received, ..
+            } => assert_eq!(received, groups),
+            message => panic!("Unexpected message {message:?}"),
+        }
+
+        let bans: Vec<BannedImage> = (0..2000)
+            .map(|ii| BannedImage {
+                path: format!("/home/user/Pictures/wallpapers/banned/image-{ii:06}.jpg").into(),
+                size: ii,
+                hash: ii,
+            })
+            .collect();
+        match round_trip(ServerMessage::Bans(bans.clone())) {
+            ServerMessage::Bans(received) => assert_eq!(received, bans),
+            message => panic!("Unexpected message {message:?}"),
+        }
+    }
+
+    /// Sends `message`, which should be too big for a u16 length, through a packet
+    fn round_trip(message: ServerMessage) -> ServerMessage {
+        let data = message.serialize().unwrap();
+        assert!(data.len() > 1 << 16);
+        let bytes = Packet::new(data).into_bytes();
+        let header: [u8; 8] = bytes[..8].try_into().unwrap();
+        let len = Packet::len_from_header(&header).expect("Header wasn't valid");
+        assert_eq!(len, bytes.len() - 8);
+        ServerMessage::deserialize(&bytes[8..]).unwrap()
     }
 }
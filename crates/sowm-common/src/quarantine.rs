@@ -0,0 +1,117 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the file in the state directory that quarantined images are saved to
+pub const QUARANTINE_FILE: &str = "quarantine.json";
+
+/// An image that couldn't be shown
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedImage {
+    pub path: PathBuf,
+    /// Why the image can't be shown
+    pub reason: String,
+    /// Size of the file when it was checked, if the file changes it gets checked again
+    pub size: u64,
+    /// When the file was last modified when it was checked
+    pub modified: SystemTime,
+}
+
+/// Images that failed validation and are kept from being shown until they change
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Quarantine {
+    images: Vec<QuarantinedImage>,
+}
+
+impl Quarantine {
+    /// Loads the quarantine from the file at `path`, returning an empty one if it can't be read
+    pub fn load<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the quarantine to the file at `path`
+    pub fn save<P>(&self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let data = serde_json::to_string(self).expect("Quarantine should always be serializable");
+        std::fs::write(path, data)
+    }
+
+    /// Quarantines the image at `path`, returns false if it is gone
+    pub fn add(&mut self, path: PathBuf, reason: String) -> bool {
+        let Some((size, modified)) = file_version(&path) else {
+            return false;
+        };
+        self.images.retain(|q| q.path != path);
+        self.images.push(QuarantinedImage {
+            path,
+            reason,
+            size,
+            modified,
+        });
+        true
+    }
+
+    /// If the image at `path` is quarantined and hasn't changed since
+    pub fn contains(&self, path: &Path) -> bool {
+        let Some(image) = self.images.iter().find(|q| q.path == path) else {
+            return false;
+        };
+        file_version(path) == Some((image.size, image.modified))
+    }
+
+    /// Forgets images that have been deleted or changed, returns false if there weren't any
+    pub fn prune(&mut self) -> bool {
+        let len = self.images.len();
+        self.images
+            .retain(|q| file_version(&q.path) == Some((q.size, q.modified)));
+        self.images.len() != len
+    }
+
+    /// All quarantined images
+    pub fn list(&self) -> &[QuarantinedImage] {
+        &self.images
+    }
+}
+
+/// Size and modification time of the file at `path`
+fn file_version(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = path.metadata().ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_files_leave_quarantine() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let image = dir.join("a.jpg");
+        std::fs::write(&image, b"half an image").unwrap();
+
+        let mut quarantine = Quarantine::default();
+        assert!(quarantine.add(image.clone(), "image is truncated".into()));
+        assert!(quarantine.contains(&image));
+        assert!(!quarantine.prune());
+
+        // The download finished
+        std::fs::write(&image, b"the whole of an image").unwrap();
+        assert!(!quarantine.contains(&image));
+        assert!(quarantine.prune());
+        assert!(quarantine.list().is_empty());
+
+        assert!(!quarantine.add(dir.join("missing.jpg"), "gone".into()));
+    }
+}
@@ -0,0 +1,298 @@
+use std::path::{Component, Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+#[cfg(test)]
+use walkdir::WalkDir;
+
+use crate::is_supported_image;
+
+/// Which files in an image directory are picked up
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanSettings {
+    /// Globs of the images to pick up, relative to the image directory. Every image is picked up
+    /// if there are none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Globs of files and directories to leave out, relative to the image directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Look in directories that are symbolic links, symbolic links to images are always followed
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// How many directories deep to look, 0 only looks at the images directly in the image
+    /// directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// Leave out files and directories whose name starts with a dot
+    #[serde(default)]
+    pub skip_hidden: bool,
+}
+
+/// Settings for one image directory, the ones that aren't given come from the top level
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub follow_symlinks: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_hidden: Option<bool>,
+}
+
+impl ScanOverrides {
+    /// The settings from `base` with these ones replacing them
+    pub fn apply(&self, base: &ScanSettings) -> ScanSettings {
+        ScanSettings {
+            include: self.include.clone().unwrap_or_else(|| base.include.clone()),
+            exclude: self.exclude.clone().unwrap_or_else(|| base.exclude.clone()),
+            follow_symlinks: self.follow_symlinks.unwrap_or(base.follow_symlinks),
+            max_depth: self.max_depth.or(base.max_depth),
+            skip_hidden: self.skip_hidden.unwrap_or(base.skip_hidden),
+        }
+    }
+}
+
+/// Where the images of a collection are, either one directory or a list of them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ImageDirs {
+    One(PathBuf),
+    Many(Vec<ImageDir>),
+}
+
+/// A directory in a list of image directories, either just its path or a table with its own
+/// scan settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ImageDir {
+    Path(PathBuf),
+    Table {
+        path: PathBuf,
+        #[serde(flatten)]
+        scan: ScanOverrides,
+    },
+}
+
+impl ImageDirs {
+    /// Each directory with the settings to scan it with, given the top level settings
+    pub fn settings(&self, base: &ScanSettings) -> Vec<(&Path, ScanSettings)> {
+        match self {
+            ImageDirs::One(path) => vec![(path, base.clone())],
+            ImageDirs::Many(dirs) => dirs
+                .iter()
+                .map(|dir| match dir {
+                    ImageDir::Path(path) => (path.as_path(), base.clone()),
+                    ImageDir::Table { path, scan } => (path.as_path(), scan.apply(base)),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A directory of images and how to scan it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanDir {
+    pub path: PathBuf,
+    pub options: ScanOptions,
+}
+
+/// Scan settings ready to be used
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    settings: ScanSettings,
+    /// Lower case extensions of images
+    extensions: Vec<String>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl ScanOptions {
+    /// Compiles the globs in `settings`, giving the first one that isn't valid
+    pub fn new(settings: &ScanSettings, extensions: Vec<String>) -> Result<Self, String> {
+        let include = match settings.include.is_empty() {
+            true => None,
+            false => Some(glob_set(&settings.include)?),
+        };
+        Ok(ScanOptions {
+            settings: settings.clone(),
+            extensions,
+            include,
+            exclude: glob_set(&settings.exclude)?,
+        })
+    }
+
+    pub(crate) fn settings(&self) -> &ScanSettings {
+        &self.settings
+    }
+
+    /// All the images under `root` that should be picked up, found by simply walking the
+    /// directories to check the index's scan against
+    #[cfg(test)]
+    pub(crate) fn walk(&self, root: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+        let mut walker = WalkDir::new(root).follow_links(self.settings.follow_symlinks);
+        if let Some(depth) = self.settings.max_depth {
+            walker = walker.max_depth(depth + 1);
+        }
+        let root = root.to_path_buf();
+        let filter_root = root.clone();
+        walker
+            .into_iter()
+            // Excluded and hidden directories aren't looked in at all
+            .filter_entry(move |e| e.depth() == 0 || !self.is_left_out(&filter_root, e.path()))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() || (e.path_is_symlink() && e.path().is_file()))
+            .map(|e| e.into_path())
+            .filter(move |p| self.is_image(&root, p))
+    }
+
+    /// If the file at `path` under `root` is an image that should be picked up
+    pub fn is_image(&self, root: &Path, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        if self.settings.max_depth.is_some_and(|max| depth > max + 1) {
+            return false;
+        }
+        if self.is_left_out(root, path) {
+            return false;
+        }
+        if self.include.as_ref().is_some_and(|i| !i.is_match(relative)) {
+            return false;
+        }
+        is_supported_image(path, &self.extensions)
+    }
+
+    /// If `path` or any directory it is in below `root` is hidden or excluded
+    pub(crate) fn is_left_out(&self, root: &Path, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return true;
+        };
+        if self.settings.skip_hidden
+            && relative.components().any(|c| match c {
+                Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+                _ => false,
+            })
+        {
+            return true;
+        }
+        relative
+            .ancestors()
+            .filter(|p| !p.as_os_str().is_empty())
+            .any(|p| self.exclude.is_match(p))
+    }
+}
+
+impl PartialEq for ScanOptions {
+    fn eq(&self, other: &Self) -> bool {
+        // The globs are compiled from the settings
+        self.settings == other.settings && self.extensions == other.extensions
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|e| format!("invalid glob '{glob}': {e}"))?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(toml: &str) -> ScanOptions {
+        let settings: ScanSettings = toml::from_str(toml).unwrap();
+        ScanOptions::new(&settings, vec!["jpg".into(), "png".into()]).unwrap()
+    }
+
+    #[test]
+    fn filters_apply_to_the_walk() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let files = [
+            "a.jpg",
+            "b.png",
+            "notes.txt",
+            ".hidden.jpg",
+            "@eaDir/a.jpg",
+            ".thumbnails/a.jpg",
+            "nested/c.jpg",
+            "nested/deeper/d.jpg",
+        ];
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"data").unwrap();
+        }
+        let walk = |options: ScanOptions| {
+            let mut images: Vec<String> = options
+                .walk(root)
+                .map(|p| p.strip_prefix(root).unwrap().display().to_string())
+                .collect();
+            images.sort();
+            images
+        };
+
+        assert_eq!(walk(options("")).len(), 7);
+        assert_eq!(
+            walk(options("skip_hidden = true\nexclude = [\"@eaDir\"]")),
+            ["a.jpg", "b.png", "nested/c.jpg", "nested/deeper/d.jpg"]
+        );
+        assert_eq!(
+            walk(options(
+                "max_depth = 1\nskip_hidden = true\ninclude = [\"**/*.jpg\"]"
+            )),
+            ["@eaDir/a.jpg", "a.jpg", "nested/c.jpg"]
+        );
+    }
+
+    #[test]
+    fn directories_override_settings() {
+        #[derive(Deserialize)]
+        struct Dirs {
+            image_dir: ImageDirs,
+        }
+        let toml = "image_dir = [\"/local\", { path = \"/mnt/nas\", exclude = [\"**/@eaDir\"] }]";
+        let dirs: Dirs = toml::from_str(toml).unwrap();
+        let base = ScanSettings {
+            exclude: vec!["*.tmp".into()],
+            skip_hidden: true,
+            ..Default::default()
+        };
+        let settings = dirs.image_dir.settings(&base);
+        assert_eq!(settings[0], (Path::new("/local"), base.clone()));
+        assert_eq!(settings[1].0, Path::new("/mnt/nas"));
+        assert_eq!(settings[1].1.exclude, ["**/@eaDir"]);
+        assert!(settings[1].1.skip_hidden);
+
+        let dirs: Dirs = toml::from_str("image_dir = \"/local\"").unwrap();
+        assert_eq!(dirs.image_dir, ImageDirs::One("/local".into()));
+    }
+
+    #[test]
+    fn watched_paths_follow_the_same_rules() {
+        let options = options("skip_hidden = true\nexclude = [\"**/@eaDir\"]\nmax_depth = 0");
+        let root = Path::new("/images");
+        assert!(options.is_image(root, Path::new("/images/a.jpg")));
+        assert!(!options.is_image(root, Path::new("/images/sub/a.jpg")));
+        assert!(!options.is_image(root, Path::new("/images/.a.jpg")));
+        assert!(!options.is_image(root, Path::new("/images/@eaDir/a.jpg")));
+        assert!(!options.is_image(root, Path::new("/elsewhere/a.jpg")));
+
+        assert!(ScanOptions::new(
+            &ScanSettings {
+                exclude: vec!["[".into()],
+                ..Default::default()
+            },
+            Vec::new()
+        )
+        .is_err());
+    }
+}
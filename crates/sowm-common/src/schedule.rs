@@ -0,0 +1,380 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// A time of day with minute precision, written as `HH:MM` in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    /// Minutes since midnight, 24:00 is allowed to mean the end of the day
+    minutes: u16,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u16, minute: u16) -> Option<Self> {
+        if minute >= 60 || hour > 24 || (hour == 24 && minute != 0) {
+            return None;
+        }
+        Some(TimeOfDay {
+            minutes: hour * 60 + minute,
+        })
+    }
+
+    /// Minutes since midnight
+    pub fn minutes(&self) -> u16 {
+        self.minutes
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid time of day '{s}', expected HH:MM");
+        let (hour, minute) = s.trim().split_once(':').ok_or_else(err)?;
+        let hour = hour.parse().map_err(|_| err())?;
+        let minute = minute.parse().map_err(|_| err())?;
+        TimeOfDay::new(hour, minute).ok_or_else(err)
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(t: TimeOfDay) -> Self {
+        t.to_string()
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// Shows a collection between two times of the day. If `to` is before `from` the window wraps
+/// past midnight.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleRule {
+    pub from: TimeOfDay,
+    pub to: TimeOfDay,
+    pub collection: String,
+}
+
+impl ScheduleRule {
+    /// If `time` is inside the window, the start of the window is inclusive and the end exclusive
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        match self.from.cmp(&self.to) {
+            std::cmp::Ordering::Less => self.from <= time && time < self.to,
+            std::cmp::Ordering::Greater => time >= self.from || time < self.to,
+            // A window that starts when it ends covers the whole day
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+}
+
+impl Display for ScheduleRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "schedule {}-{} ({})",
+            self.from, self.to, self.collection
+        )
+    }
+}
+
+/// Shows collections depending on where the sun is at a location, the times are worked out
+/// locally so no network connection is needed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SolarSchedule {
+    /// Degrees north of the equator, negative for the southern hemisphere
+    pub latitude: f64,
+    /// Degrees east of Greenwich, negative for the western hemisphere
+    pub longitude: f64,
+    /// Collection shown between sunrise and sunset
+    #[serde(default = "default_day")]
+    pub day: String,
+    /// Collection shown during civil twilight, before sunrise and after sunset
+    #[serde(default = "default_dusk")]
+    pub dusk: String,
+    /// Collection shown the rest of the time
+    #[serde(default = "default_night")]
+    pub night: String,
+}
+
+fn default_day() -> String {
+    "day".into()
+}
+
+fn default_dusk() -> String {
+    "dusk".into()
+}
+
+fn default_night() -> String {
+    "night".into()
+}
+
+impl SolarSchedule {
+    /// If the location is somewhere on earth
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+
+    /// Names of all collections used by the schedule
+    pub fn collections(&self) -> [&str; 3] {
+        [&self.day, &self.dusk, &self.night]
+    }
+}
+
+/// Gets the first rule whose window contains `time`
+pub fn active_rule(rules: &[ScheduleRule], time: TimeOfDay) -> Option<&ScheduleRule> {
+    rules.iter().find(|r| r.contains(time))
+}
+
+/// A day written as `MM-DD` for every year or `YYYY-MM-DD` for one year only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Day {
+    year: Option<i32>,
+    month: u32,
+    day: u32,
+}
+
+impl Day {
+    /// Month and day, for comparing days that repeat every year
+    fn month_day(&self) -> (u32, u32) {
+        (self.month, self.day)
+    }
+
+    /// The day as a date if it is only in one year
+    fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year?, self.month, self.day)
+    }
+
+    /// If `date` is this day
+    pub fn matches(&self, date: NaiveDate) -> bool {
+        self.year.is_none_or(|y| y == date.year()) && self.month_day() == (date.month(), date.day())
+    }
+}
+
+impl FromStr for Day {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid day '{s}', expected MM-DD or YYYY-MM-DD");
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        let (year, month, day) = match parts[..] {
+            [month, day] => (None, month, day),
+            [year, month, day] => (Some(year.parse().map_err(|_| err())?), month, day),
+            _ => return Err(err()),
+        };
+        let month = month.parse().map_err(|_| err())?;
+        let day = day.parse().map_err(|_| err())?;
+        // Check against a leap year so 02-29 is allowed for days that repeat every year
+        NaiveDate::from_ymd_opt(year.unwrap_or(2000), month, day).ok_or_else(err)?;
+        Ok(Day { year, month, day })
+    }
+}
+
+impl TryFrom<String> for Day {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Day> for String {
+    fn from(d: Day) -> Self {
+        d.to_string()
+    }
+}
+
+impl Display for Day {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(year) = self.year {
+            write!(f, "{year:04}-")?;
+        }
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+/// The days a date rule applies on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Dates {
+    /// Every day from `from` to `to` inclusive. Ranges of days that repeat every year wrap past
+    /// the end of the year if `to` is before `from`.
+    Range { from: Day, to: Day },
+    /// A single day
+    On { on: Day },
+}
+
+/// Shows a collection on certain days of the year
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateRule {
+    #[serde(flatten)]
+    pub dates: Dates,
+    pub collection: String,
+}
+
+impl DateRule {
+    /// If the rule applies on `date`
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        match &self.dates {
+            Dates::On { on } => on.matches(date),
+            Dates::Range { from, to } => match (from.date(), to.date()) {
+                (Some(from), Some(to)) => from <= date && date <= to,
+                _ => {
+                    let (from, to) = (from.month_day(), to.month_day());
+                    let day = (date.month(), date.day());
+                    if from <= to {
+                        from <= day && day <= to
+                    } else {
+                        day >= from || day <= to
+                    }
+                }
+            },
+        }
+    }
+
+    /// Checks that both ends of a range either have a year or don't
+    pub fn is_valid(&self) -> Result<(), String> {
+        match &self.dates {
+            Dates::Range { from, to } if from.year.is_some() != to.year.is_some() => Err(format!(
+                "Date range {from} to {to} should give a year for both days or neither"
+            )),
+            Dates::Range { from, to } if from.year.is_some() && from.date() > to.date() => {
+                Err(format!("Date range {from} to {to} is backwards"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for DateRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.dates {
+            Dates::Range { from, to } => write!(f, "dates {from} to {to}")?,
+            Dates::On { on } => write!(f, "date {on}")?,
+        }
+        write!(f, " ({})", self.collection)
+    }
+}
+
+/// Gets the first date rule that applies on `date`
+pub fn active_date_rule(rules: &[DateRule], date: NaiveDate) -> Option<&DateRule> {
+    rules.iter().find(|r| r.contains(date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> TimeOfDay {
+        s.parse().unwrap()
+    }
+
+    fn rule(from: &str, to: &str, collection: &str) -> ScheduleRule {
+        ScheduleRule {
+            from: time(from),
+            to: time(to),
+            collection: collection.into(),
+        }
+    }
+
+    #[test]
+    fn parse_time_of_day() {
+        assert_eq!(time("07:05").minutes(), 7 * 60 + 5);
+        assert_eq!(time("24:00").minutes(), 24 * 60);
+        assert_eq!(time("7:05").to_string(), "07:05");
+        assert!("24:01".parse::<TimeOfDay>().is_err());
+        assert!("12:60".parse::<TimeOfDay>().is_err());
+        assert!("noon".parse::<TimeOfDay>().is_err());
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn date_rule(toml: &str) -> DateRule {
+        toml::from_str(&format!("{toml}\ncollection = \"c\"")).unwrap()
+    }
+
+    #[test]
+    fn parse_day() {
+        assert_eq!("12-25".parse::<Day>().unwrap().to_string(), "12-25");
+        assert_eq!("2025-1-2".parse::<Day>().unwrap().to_string(), "2025-01-02");
+        assert!("02-29".parse::<Day>().is_ok());
+        assert!("2025-02-29".parse::<Day>().is_err());
+        assert!("13-01".parse::<Day>().is_err());
+        assert!("christmas".parse::<Day>().is_err());
+    }
+
+    #[test]
+    fn date_range_wraps_past_year_end() {
+        let winter = date_rule("from = \"12-01\"\nto = \"02-28\"");
+        assert!(winter.contains(date("2024-12-01")));
+        assert!(winter.contains(date("2025-01-15")));
+        assert!(winter.contains(date("2025-02-28")));
+        assert!(!winter.contains(date("2025-03-01")));
+        assert!(!winter.contains(date("2025-11-30")));
+    }
+
+    #[test]
+    fn specific_dates() {
+        let christmas = date_rule("on = \"12-25\"");
+        assert!(christmas.contains(date("2030-12-25")));
+        assert!(!christmas.contains(date("2030-12-26")));
+
+        let launch = date_rule("on = \"2025-06-01\"");
+        assert!(launch.contains(date("2025-06-01")));
+        assert!(!launch.contains(date("2026-06-01")));
+
+        let trip = date_rule("from = \"2025-12-20\"\nto = \"2026-01-05\"");
+        assert!(trip.is_valid().is_ok());
+        assert!(trip.contains(date("2026-01-01")));
+        assert!(!trip.contains(date("2027-01-01")));
+
+        assert!(date_rule("from = \"2025-12-20\"\nto = \"01-05\"")
+            .is_valid()
+            .is_err());
+        assert!(date_rule("from = \"2026-01-05\"\nto = \"2025-12-20\"")
+            .is_valid()
+            .is_err());
+    }
+
+    #[test]
+    fn window_wraps_past_midnight() {
+        let night = rule("18:00", "07:00", "night");
+        assert!(night.contains(time("18:00")));
+        assert!(night.contains(time("23:59")));
+        assert!(night.contains(time("00:00")));
+        assert!(!night.contains(time("07:00")));
+        assert!(!night.contains(time("12:00")));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            rule("12:00", "13:00", "lunch"),
+            rule("09:00", "17:00", "work"),
+        ];
+        assert_eq!(
+            active_rule(&rules, time("12:30")).unwrap().collection,
+            "lunch"
+        );
+        assert_eq!(
+            active_rule(&rules, time("10:00")).unwrap().collection,
+            "work"
+        );
+        assert!(active_rule(&rules, time("20:00")).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    ffi::OsStr,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use sowm_common::{bans::content_hash, cache_file, format::Format, Backend};
+
+/// Directory in the cache that converted images are kept in
+const CONVERTED_DIR: &str = "converted";
+
+/// Converts images the backend can't show into PNGs kept in the cache directory
+pub struct Converter {
+    backend: Backend,
+    /// Where converted images are kept, if the cache directory is available
+    dir: Option<PathBuf>,
+}
+
+impl Converter {
+    pub fn new(backend: Backend) -> Self {
+        let dir = cache_file(CONVERTED_DIR)
+            .map_err(|e| e.to_string())
+            .and_then(|dir| match std::fs::create_dir_all(&dir) {
+                Ok(()) => Ok(dir),
+                Err(e) => Err(format!("{}: {e}", dir.display())),
+            });
+        let dir = match dir {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                eprintln!("Images the backend can't show won't be converted: {e}");
+                None
+            }
+        };
+        Converter { backend, dir }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The file to give the backend to show the image at `path`, converting the image if the
+    /// backend can't show it. Converted images are kept so each image is only converted once.
+    pub fn displayable(&self, path: &Path) -> Result<PathBuf, String> {
+        let format = match Format::detect(path) {
+            Ok(Some(format)) => format,
+            // Let the backend have a go at anything else
+            Ok(None) => return Ok(path.to_path_buf()),
+            Err(e) => return Err(e.to_string()),
+        };
+        if self.backend.displays(format) {
+            return Ok(path.to_path_buf());
+        }
+        let Some(dir) = &self.dir else {
+            return Err(format!(
+                "{format} images can't be converted without a cache"
+            ));
+        };
+
+        // Named after the contents so the image is converted again if it changes
+        let hash = content_hash(path).map_err(|e| e.to_string())?;
+        let converted = dir.join(format!("{hash:016x}.png"));
+        if converted.is_file() {
+            return Ok(converted);
+        }
+        // Convert to a temporary file so a failed conversion doesn't leave half an image behind
+        let partial = dir.join(format!("{hash:016x}.partial.png"));
+        convert(path, &partial).map_err(|e| format!("failed to convert {format} image: {e}"))?;
+        std::fs::rename(&partial, &converted).map_err(|e| e.to_string())?;
+        Ok(converted)
+    }
+}
+
+/// Runs ImageMagick with `args`
+pub fn magick(args: &[&OsStr]) -> std::io::Result<Output> {
+    // ImageMagick 7 is `magick`, older versions only have `convert`
+    match Command::new("magick").args(args).output() {
+        Err(e) if e.kind() == ErrorKind::NotFound => Command::new("convert").args(args).output(),
+        output => output,
+    }
+}
+
+/// Converts the image at `from` to the format of `to`'s extension with ImageMagick
+fn convert(from: &Path, to: &Path) -> Result<(), String> {
+    match magick(&[from.as_os_str(), to.as_os_str()]) {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => {
+            let _ = std::fs::remove_file(to);
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+        Err(e) => Err(format!("couldn't run ImageMagick: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_unsupported_formats_are_converted() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let gif = dir.join("a.gif");
+        let avif = dir.join("b.avif");
+        std::fs::write(&gif, b"GIF89a\x01\x00\x01\x00").unwrap();
+        std::fs::write(&avif, b"\x00\x00\x00\x1cftypavif").unwrap();
+
+        let converter = Converter {
+            backend: Backend::Feh,
+            dir: None,
+        };
+        assert_eq!(converter.displayable(&gif), Ok(gif));
+        assert!(converter.displayable(&avif).is_err());
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+};
+
+use sowm_common::{
+    bans::content_hash,
+    header::{check_image, Dimensions, ImageInfo},
+    index::Index,
+};
+
+use crate::convert::magick;
+
+/// Width and height the image is shrunk to for its perceptual hash, one pixel wider than the
+/// hash so each pixel can be compared with the next one
+const HASH_WIDTH: usize = 9;
+const HASH_HEIGHT: usize = 8;
+
+/// Perceptual hashes with fewer bits set than this, or more than 64 minus this, come from images
+/// with too little detail to tell apart, like plain colours, so they are only grouped when their
+/// contents are the same
+const MIN_HASH_DETAIL: u32 = 4;
+
+/// What duplicates are found from
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hashes {
+    pub content: Option<u64>,
+    pub perceptual: Option<u64>,
+    pub dimensions: Option<Dimensions>,
+}
+
+/// Works out the hashes of images on another thread, as decoding every image of a big library
+/// takes a while, and keeps them in the index
+pub struct Hasher {
+    jobs: Sender<Job>,
+    hashed: Receiver<Hashed>,
+    /// Images sent to the thread that haven't come back yet
+    pending: HashSet<PathBuf>,
+    /// If ImageMagick can be run to work out perceptual hashes
+    available: Arc<AtomicBool>,
+    /// Images that couldn't be hashed, so they aren't tried again
+    failed: HashSet<PathBuf>,
+}
+
+/// An image for the hashing thread, with which of its hashes the index doesn't have yet
+struct Job {
+    path: PathBuf,
+    info: bool,
+    content: bool,
+    perceptual: bool,
+}
+
+/// What the hashing thread worked out for an image, `None` for what it wasn't asked for
+struct Hashed {
+    path: PathBuf,
+    info: Option<ImageInfo>,
+    content: Option<u64>,
+    perceptual: Option<u64>,
+    /// If the image couldn't be read
+    failed: bool,
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        let (jobs, rx) = channel::<Job>();
+        let (tx, hashed) = channel();
+        let available = Arc::new(AtomicBool::new(true));
+        let thread_available = available.clone();
+        std::thread::spawn(move || {
+            for job in rx {
+                // The engine has stopped if nobody is listening
+                if tx.send(job.run(&thread_available)).is_err() {
+                    break;
+                }
+            }
+        });
+        Hasher {
+            jobs,
+            hashed,
+            pending: HashSet::new(),
+            available,
+            failed: HashSet::new(),
+        }
+    }
+
+    /// If the image at `path` is indexed and has hashes still to be worked out
+    pub fn needs_hashing(&self, index: &Index, path: &Path) -> bool {
+        let Some(file) = index.get(path) else {
+            return false;
+        };
+        !self.failed.contains(path)
+            && (file.info.is_none()
+                || file.hash.is_none()
+                || (self.available.load(Ordering::Relaxed) && file.perceptual.is_none()))
+    }
+
+    /// Sends the image at `path` to be hashed if the index doesn't have all of its hashes and it
+    /// isn't waiting to be hashed already
+    pub fn queue(&mut self, index: &Index, path: &Path) {
+        if self.pending.contains(path) || !self.needs_hashing(index, path) {
+            return;
+        }
+        let Some(file) = index.get(path) else {
+            return;
+        };
+        let job = Job {
+            path: path.to_path_buf(),
+            info: file.info.is_none(),
+            content: file.hash.is_none(),
+            perceptual: file.perceptual.is_none(),
+        };
+        if self.jobs.send(job).is_ok() {
+            self.pending.insert(path.to_path_buf());
+        }
+    }
+
+    /// Puts the hashes the thread has worked out so far in the index, returning true if there
+    /// were any
+    pub fn receive(&mut self, index: &mut Index) -> bool {
+        let mut received = false;
+        while let Ok(hashed) = self.hashed.try_recv() {
+            received = true;
+            let path = &hashed.path;
+            self.pending.remove(path);
+            if let Some(info) = hashed.info {
+                index.set_info(path, info);
+            }
+            if let Some(hash) = hashed.content {
+                index.set_hash(path, hash);
+            }
+            if let Some(hash) = hashed.perceptual {
+                index.set_perceptual(path, hash);
+            }
+            if hashed.failed {
+                self.failed.insert(hashed.path);
+            }
+        }
+        received
+    }
+
+    /// Number of images waiting to be hashed
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Stops waiting for the images sent to be hashed, their hashes still go in the index when
+    /// they come back
+    pub fn forget(&mut self) {
+        self.pending.clear();
+    }
+}
+
+impl Job {
+    /// Works out the hashes that were asked for. The perceptual hash is left out once ImageMagick
+    /// turns out not to be `available`.
+    fn run(self, available: &AtomicBool) -> Hashed {
+        let mut hashed = Hashed {
+            info: None,
+            content: None,
+            perceptual: None,
+            failed: false,
+            path: self.path,
+        };
+        let path = &hashed.path;
+        if self.info {
+            match check_image(path) {
+                Ok(info) => hashed.info = Some(info),
+                Err(_) => hashed.failed = true,
+            }
+        }
+        if self.content && !hashed.failed {
+            match content_hash(path) {
+                Ok(hash) => hashed.content = Some(hash),
+                Err(_) => hashed.failed = true,
+            }
+        }
+        if !self.perceptual || hashed.failed || !available.load(Ordering::Relaxed) {
+            return hashed;
+        }
+        match perceptual_hash(path) {
+            Ok(Some(hash)) => hashed.perceptual = Some(hash),
+            Ok(None) => hashed.failed = true,
+            Err(e) => {
+                eprintln!(
+                    "Only exact copies of images will be found, couldn't run ImageMagick: {e}"
+                );
+                available.store(false, Ordering::Relaxed);
+            }
+        }
+        hashed
+    }
+}
+
+/// Works out the perceptual hash of the image at `path` by shrinking it to a few grey pixels and
+/// comparing each pixel with the one to its right. `None` if ImageMagick couldn't read the image.
+fn perceptual_hash(path: &Path) -> std::io::Result<Option<u64>> {
+    // Only the first frame of animated images
+    let mut input = path.as_os_str().to_os_string();
+    input.push("[0]");
+    let size = format!("{HASH_WIDTH}x{HASH_HEIGHT}!");
+    let args = [
+        input.as_os_str(),
+        "-colorspace".as_ref(),
+        "Gray".as_ref(),
+        "-resize".as_ref(),
+        size.as_ref(),
+        "-depth".as_ref(),
+        "8".as_ref(),
+        "gray:-".as_ref(),
+    ];
+    let output = magick(&args)?;
+    match output.status.success() {
+        true => Ok(difference_hash(&output.stdout)),
+        false => Ok(None),
+    }
+}
+
+/// Hash of a `HASH_WIDTH` by `HASH_HEIGHT` image with one byte per pixel, a bit is set where a
+/// pixel is darker than the one to its right
+fn difference_hash(pixels: &[u8]) -> Option<u64> {
+    if pixels.len() != HASH_WIDTH * HASH_HEIGHT {
+        return None;
+    }
+    let mut hash = 0;
+    for row in pixels.chunks(HASH_WIDTH) {
+        for pair in row.windows(2) {
+            hash = (hash << 1) | (pair[0] < pair[1]) as u64;
+        }
+    }
+    Some(hash)
+}
+
+/// Groups of images that are copies of each other
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    /// Each group has the biggest image first
+    groups: Vec<Vec<PathBuf>>,
+    /// Which group each image with copies is in
+    group_of: HashMap<PathBuf, usize>,
+    /// Sizes of the images in groups, if they are known
+    dimensions: HashMap<PathBuf, Dimensions>,
+}
+
+impl Duplicates {
+    /// Groups images whose contents are the same, or whose perceptual hashes differ in at most
+    /// `max_distance` bits
+    pub fn find(images: Vec<(PathBuf, Hashes)>, max_distance: u32) -> Self {
+        let mut sets = DisjointSets::new(images.len());
+
+        let mut by_content: HashMap<u64, usize> = HashMap::new();
+        for (ii, (_, hashes)) in images.iter().enumerate() {
+            if let Some(hash) = hashes.content {
+                let first = *by_content.entry(hash).or_insert(ii);
+                sets.union(first, ii);
+            }
+        }
+
+        // Hashes that differ in at most `max_distance` bits are the same in at least one of
+        // `max_distance + 1` chunks, so only hashes sharing a chunk need comparing
+        let chunks = max_distance as usize + 1;
+        let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+        for (ii, (_, hashes)) in images.iter().enumerate() {
+            let Some(hash) = hashes.perceptual else {
+                continue;
+            };
+            let detail = hash.count_ones();
+            if !(MIN_HASH_DETAIL..=64 - MIN_HASH_DETAIL).contains(&detail) {
+                continue;
+            }
+            for chunk in 0..chunks {
+                let (start, end) = (chunk * 64 / chunks, (chunk + 1) * 64 / chunks);
+                let bits = (hash >> start) & (u64::MAX >> (64 - (end - start)));
+                buckets.entry((chunk, bits)).or_default().push(ii);
+            }
+        }
+        for bucket in buckets.values() {
+            for (jj, a) in bucket.iter().enumerate() {
+                for b in bucket[jj + 1..].iter() {
+                    let (Some(x), Some(y)) = (images[*a].1.perceptual, images[*b].1.perceptual)
+                    else {
+                        continue;
+                    };
+                    if (x ^ y).count_ones() <= max_distance {
+                        sets.union(*a, *b);
+                    }
+                }
+            }
+        }
+
+        let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+        for ii in 0..images.len() {
+            members.entry(sets.find(ii)).or_default().push(ii);
+        }
+        let mut duplicates = Duplicates::default();
+        let mut groups: Vec<Vec<PathBuf>> = Vec::new();
+        for mut group in members.into_values().filter(|m| m.len() > 1) {
+            let pixels = |ii: usize| images[ii].1.dimensions.map_or(0, pixels);
+            group.sort_by(|a, b| {
+                let by_path = || images[*a].0.cmp(&images[*b].0);
+                pixels(*b).cmp(&pixels(*a)).then_with(by_path)
+            });
+            for ii in group.iter() {
+                if let Some(dimensions) = images[*ii].1.dimensions {
+                    duplicates
+                        .dimensions
+                        .insert(images[*ii].0.clone(), dimensions);
+                }
+            }
+            groups.push(group.into_iter().map(|ii| images[ii].0.clone()).collect());
+        }
+        groups.sort();
+        for (ii, group) in groups.iter().enumerate() {
+            for path in group {
+                duplicates.group_of.insert(path.clone(), ii);
+            }
+        }
+        duplicates.groups = groups;
+        duplicates
+    }
+
+    pub fn groups(&self) -> &[Vec<PathBuf>] {
+        &self.groups
+    }
+
+    /// Size of an image in a group, if it is known
+    pub fn dimensions(&self, path: &Path) -> Option<Dimensions> {
+        self.dimensions.get(path).copied()
+    }
+
+    /// The image at `path` and its copies, empty if it doesn't have any
+    pub fn copies(&self, path: &Path) -> &[PathBuf] {
+        match self.group_of.get(path) {
+            Some(ii) => &self.groups[*ii],
+            None => &[],
+        }
+    }
+
+    /// `paths` along with all of their copies
+    pub fn with_copies(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut all = paths.to_vec();
+        for path in paths {
+            all.extend(self.copies(path).iter().cloned());
+        }
+        all
+    }
+
+    /// Keeps one image from each group of copies in `images`, the biggest one, so the group is
+    /// only shown as often as any other image
+    pub fn dedupe(&self, images: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut best: HashMap<usize, usize> = HashMap::new();
+        for image in images.iter() {
+            if let Some(group) = self.group_of.get(image) {
+                let rank = self.groups[*group].iter().position(|p| p == image);
+                let rank = rank.expect("Image should be in its group");
+                best.entry(*group)
+                    .and_modify(|b| *b = (*b).min(rank))
+                    .or_insert(rank);
+            }
+        }
+        images
+            .into_iter()
+            .filter(|image| match self.group_of.get(image) {
+                Some(group) => self.groups[*group][best[group]] == *image,
+                None => true,
+            })
+            .collect()
+    }
+
+    /// The copy of the image at `path` to show on a monitor of size `monitor`, out of the copies
+    /// that `usable` accepts. Copies with the closest aspect ratio to the monitor are preferred,
+    /// then the biggest one. Only the biggest copy is preferred if the monitor's size isn't
+    /// known.
+    pub fn best_copy<F>(&self, path: &Path, monitor: Option<Dimensions>, mut usable: F) -> PathBuf
+    where
+        F: FnMut(&Path) -> bool,
+    {
+        let mut copies = self.copies(path).iter().filter(|p| usable(p));
+        let best = match monitor {
+            Some(monitor) => copies.min_by_key(|p| {
+                // Differences of under a percent don't count, so a bigger copy can win
+                let difference = self.dimensions(p).map_or(u64::MAX, |d| {
+                    (aspect_difference(d, monitor) * 100.0).round() as u64
+                });
+                (
+                    difference,
+                    std::cmp::Reverse(self.dimensions(p).map_or(0, pixels)),
+                )
+            }),
+            // Groups have the biggest copy first
+            None => copies.next(),
+        };
+        best.cloned().unwrap_or_else(|| path.to_path_buf())
+    }
+}
+
+fn pixels(dimensions: Dimensions) -> u64 {
+    dimensions.width as u64 * dimensions.height as u64
+}
+
+/// How far apart the aspect ratios of `a` and `b` are, as a fraction of `b`'s
+fn aspect_difference(a: Dimensions, b: Dimensions) -> f64 {
+    if a.height == 0 || b.height == 0 {
+        return f64::INFINITY;
+    }
+    let a = a.width as f64 / a.height as f64;
+    let b = b.width as f64 / b.height as f64;
+    (a / b - 1.0).abs()
+}
+
+/// Union-find over indices, for joining images into groups
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(len: usize) -> Self {
+        DisjointSets {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, ii: usize) -> usize {
+        let mut root = ii;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // Point everything on the way straight at the root so later finds are quick
+        let mut ii = ii;
+        while self.parents[ii] != root {
+            let next = self.parents[ii];
+            self.parents[ii] = root;
+            ii = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[b] = a;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_are_grouped() {
+        let size = |width, height| Some(Dimensions { width, height });
+        let image = |path: &str, content, perceptual, dimensions| {
+            let hashes = Hashes {
+                content: Some(content),
+                perceptual: Some(perceptual),
+                dimensions,
+            };
+            (PathBuf::from(path), hashes)
+        };
+        let detailed = 0x0f0f_3c3c_5a5a_a5a5;
+        let images = vec![
+            image("small.jpg", 1, detailed, size(1920, 1080)),
+            image("big.jpg", 2, detailed ^ 0b101, size(3840, 2160)),
+            image("square.jpg", 3, detailed ^ 0b1, size(2000, 2000)),
+            image("copy.jpg", 4, 0, size(10, 10)),
+            image("other_copy.jpg", 4, u64::MAX, size(10, 10)),
+            image("plain.jpg", 5, 0, size(10, 10)),
+            image("different.jpg", 6, !detailed, size(1920, 1080)),
+        ];
+        let duplicates = Duplicates::find(images, 4);
+        let paths = |names: &[&str]| -> Vec<PathBuf> { names.iter().map(PathBuf::from).collect() };
+        assert_eq!(
+            duplicates.groups(),
+            [
+                paths(&["big.jpg", "square.jpg", "small.jpg"]),
+                paths(&["copy.jpg", "other_copy.jpg"])
+            ]
+        );
+
+        let images = paths(&["small.jpg", "square.jpg", "plain.jpg", "other_copy.jpg"]);
+        assert_eq!(
+            duplicates.dedupe(images),
+            paths(&["square.jpg", "plain.jpg", "other_copy.jpg"])
+        );
+
+        let small = Path::new("small.jpg");
+        let monitor = size(2560, 1440);
+        assert_eq!(
+            duplicates.best_copy(small, monitor, |_| true),
+            Path::new("big.jpg")
+        );
+        assert_eq!(
+            duplicates.best_copy(small, size(1000, 1000), |_| true),
+            Path::new("square.jpg")
+        );
+        assert_eq!(
+            duplicates.best_copy(small, monitor, |p| p != Path::new("big.jpg")),
+            small
+        );
+        assert_eq!(
+            duplicates.best_copy(Path::new("plain.jpg"), monitor, |_| true),
+            Path::new("plain.jpg")
+        );
+
+        let gradient: Vec<u8> = (0..72).map(|ii| (ii % 9) as u8).collect();
+        assert_eq!(difference_hash(&gradient), Some(u64::MAX));
+        assert_eq!(difference_hash(&[0; 10]), None);
+    }
+}
@@ -1,11 +1,68 @@
 use std::{
     path::{Path, PathBuf},
-    sync::mpsc::Receiver,
-    time::{Duration, Instant},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, SystemTime},
 };
 
-use rand::{seq::SliceRandom, thread_rng};
-use sowm_common::{ClientMessage, Init};
+use sowm_common::{
+    bans::{Bans, BANS_FILE},
+    cache_file, data_file,
+    index::{Index, INDEX_FILE},
+    is_supported_image,
+    quarantine::{Quarantine, QUARANTINE_FILE},
+    state_file, ClientMessage, Config, Fallback, Fit, Init, MonitorStatus, ServerMessage,
+    SowmError, Status, SwitchTiming, DEFAULT_COLLECTION, MAX_RATING, MAX_STATUS_QUARANTINED,
+};
+
+use crate::{
+    convert::Converter,
+    dupes::{Duplicates, Hasher},
+    history::History,
+    looping_iter::LoopingIter,
+    monitors::{self, Output},
+    ratings::Ratings,
+    snapshot::{MonitorSnapshot, Snapshot},
+    timer::next_switch_time,
+    watcher::DirWatcher,
+};
+
+/// Banning images and letting them be shown again
+mod ban;
+/// Collections of images and the schedule rules that pick them
+mod collection;
+/// Finding copies of the same image in the collections
+mod duplicates;
+/// Images being added, removed and quarantined
+mod images;
+/// Rating images
+mod rate;
+/// Reloading the config and images
+mod reload;
+
+use collection::{own_collection, watch_collections, ActiveRule, OwnCollection, Scanned};
+use reload::Reloading;
+pub use reload::{load, Loaded};
+
+/// Name of the file in the state directory that the history is saved to
+const HISTORY_FILE: &str = "history.json";
+/// Name of the file in the data directory that ratings are saved to
+const RATINGS_FILE: &str = "ratings.json";
+/// Name of the file in the state directory that the engine's state is saved to
+const SNAPSHOT_FILE: &str = "engine.json";
+
+/// A message from a client along with a channel to send the response back on
+pub struct Request {
+    pub message: ClientMessage,
+    pub reply: Sender<ServerMessage>,
+}
+
+impl Request {
+    /// A request from inside the daemon, where nobody waits for the response
+    pub fn internal(message: ClientMessage) -> Self {
+        let (reply, _) = channel();
+        Request { message, reply }
+    }
+}
 
 #[derive(Debug, Clone)]
 enum State {
@@ -13,127 +70,847 @@ enum State {
     Stopped,
 }
 
-pub struct LoopingIter {
-    arr: Vec<PathBuf>,
-    ii: usize,
+/// Cycles the images on one monitor
+struct Monitor {
+    state: State,
+    /// Images the monitor shows instead of the shared collection, if it has its own
+    own: Option<OwnCollection>,
+    switch_timing: SwitchTiming,
+    /// When the timer last switched the monitor
+    last_switch: SystemTime,
+    /// When the timer should next switch the monitor, `None` if it never will
+    next_switch: Option<SystemTime>,
 }
 
-impl LoopingIter {
-    fn new(arr: Vec<PathBuf>) -> Self {
-        LoopingIter { arr, ii: 0 }
+impl Monitor {
+    /// If the monitor is running and it is time to switch
+    fn is_due(&self, now: SystemTime) -> bool {
+        if !matches!(self.state, State::Running) {
+            return false;
+        }
+        // If the clock went backwards we can't tell how long it has been, so switch anyway
+        now < self.last_switch || self.next_switch.is_some_and(|t| now >= t)
     }
-}
-
-impl Iterator for LoopingIter {
-    type Item = PathBuf;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.ii >= self.arr.len() {
-            self.ii = 0;
-        }
-        let image = self.arr.get(self.ii).cloned();
-        self.ii += 1;
-        image
+    /// Counts the time until the next switch from now
+    fn restart_timer(&mut self) {
+        self.last_switch = SystemTime::now();
+        self.next_switch = next_switch_time(&self.switch_timing, self.last_switch);
     }
 }
 
 struct Engine {
+    /// Images from the shared collection, for monitors that don't have their own
     images_iter: LoopingIter,
-    state: State,
-    wallpaper_change_dur: Duration,
-    #[allow(dead_code)]
+    monitors: Vec<Monitor>,
+    /// Output names and sizes of the monitors, empty if they aren't needed or couldn't be found
+    outputs: Vec<Output>,
+    /// What is known about the files in the image directories
+    index: Index,
+    scanned: Scanned,
+    /// Where the index gets saved, if the cache directory is available
+    index_path: Option<PathBuf>,
+    /// Images that are copies of each other, only one of each group is in the collections
+    duplicates: Duplicates,
+    /// Hashes images on another thread so copies can be found
+    hasher: Hasher,
+    /// How images are fitted to the monitors
+    fit: Fit,
+    /// Converts images the backend can't show
+    converter: Converter,
+    /// Watches the collection directories for changes, if that could be set up
+    watcher: Option<DirWatcher>,
     init: Init,
     num_monitors: usize,
+    history: History,
+    /// Where the history gets saved, if the state directory is available
+    history_path: Option<PathBuf>,
+    /// Images that stay on their monitor when cycling, indexed by monitor
+    pins: Vec<Option<PathBuf>>,
+    ratings: Ratings,
+    /// Where the ratings get saved, if the data directory is available
+    ratings_path: Option<PathBuf>,
+    bans: Bans,
+    /// Where the bans get saved, if the data directory is available
+    bans_path: Option<PathBuf>,
+    quarantine: Quarantine,
+    /// Where the quarantine gets saved, if the state directory is available
+    quarantine_path: Option<PathBuf>,
+    /// Images currently shown on each monitor
+    current: Vec<PathBuf>,
+    /// Where the engine's state gets saved, if the state directory is available
+    snapshot_path: Option<PathBuf>,
+    /// Collection the shared images are being picked from
+    collection: String,
+    /// Schedule rule that picked the collection
+    active_rule: Option<ActiveRule>,
+    /// If the fallback is shown because there are no images
+    showing_fallback: bool,
+    /// Reload waiting for its scan to finish, if one was asked for
+    reloading: Option<Reloading>,
 }
 
 impl Engine {
-    fn new(init: Init) -> Self {
-        let mut images = init.images.clone();
-        let wallpaper_change_dur = init.config.switch_interval();
+    fn new(loaded: Loaded) -> Self {
+        let Loaded {
+            mut init,
+            mut scanned,
+        } = loaded;
+        let mut index = std::mem::take(&mut init.index);
+        let index_path = saved_file(cache_file(INDEX_FILE), "Image index");
+
+        let bans_path = saved_file(data_file(BANS_FILE), "Bans");
+        let bans = bans_path.as_ref().map(Bans::load).unwrap_or_default();
+
+        let quarantine_path = saved_file(state_file(QUARANTINE_FILE), "Quarantined images");
+        let quarantine = quarantine_path
+            .as_ref()
+            .map(Quarantine::load)
+            .unwrap_or_default();
+
+        let snapshot_path = saved_file(state_file(SNAPSHOT_FILE), "Engine state");
+        let snapshot = snapshot_path.as_ref().and_then(Snapshot::load);
+
+        let active_rule = ActiveRule::now(&init.config);
+        let rule = active_rule.as_ref().map(|r| r.description.clone());
+        let mut collection = match &snapshot {
+            // Keep a collection that was picked by hand until a different rule applies
+            Some(s) if s.rule == rule && init.config.has_collection(&s.collection) => {
+                s.collection.clone()
+            }
+            _ => active_rule
+                .as_ref()
+                .map_or(DEFAULT_COLLECTION, |r| r.collection.as_str())
+                .to_string(),
+        };
+        let mut images = scanned.get(&init, &mut index, &collection, &bans);
+        if images.is_empty() && collection != DEFAULT_COLLECTION {
+            eprintln!("No images in collection {collection}, using {DEFAULT_COLLECTION}");
+            collection = DEFAULT_COLLECTION.to_string();
+            images = scanned.get(&init, &mut index, &collection, &bans);
+        }
         let num_monitors = init.config.num_monitors();
-        images.shuffle(&mut thread_rng());
-        let image_iter = LoopingIter::new(images);
+        let mut image_iter = LoopingIter::new(
+            images,
+            init.config.collection_order(&collection),
+            num_monitors,
+        );
+
+        let mut pins = vec![None; num_monitors];
+        let mut current = Vec::new();
+        // Switch straight away unless there is a saved time to carry on from
+        let mut timers = vec![
+            MonitorSnapshot {
+                running: true,
+                last_switch: SystemTime::UNIX_EPOCH,
+            };
+            num_monitors
+        ];
+
+        if let Some(snapshot) = snapshot {
+            if snapshot.order == image_iter.order() && snapshot.collection == collection {
+                image_iter.restore(&snapshot.images, snapshot.position);
+            }
+            pins = snapshot.pins;
+            pins.resize(num_monitors, None);
+            if snapshot.current.len() == num_monitors
+                && snapshot.current.iter().all(|p| p.is_file())
+            {
+                current = snapshot.current;
+            }
+            for (ii, timer) in timers.iter_mut().enumerate() {
+                *timer = snapshot
+                    .monitors
+                    .get(ii)
+                    .copied()
+                    .unwrap_or(MonitorSnapshot {
+                        running: snapshot.running,
+                        last_switch: snapshot.last_switch,
+                    });
+            }
+        }
 
-        let state = State::Running;
+        let history_path = saved_file(state_file(HISTORY_FILE), "History");
+        let history = history_path
+            .as_ref()
+            .map(|p| History::load(p, num_monitors))
+            .unwrap_or_default();
 
-        Engine {
+        let ratings_path = saved_file(data_file(RATINGS_FILE), "Ratings");
+        let ratings = ratings_path.as_ref().map(Ratings::load).unwrap_or_default();
+        image_iter.set_weights(|p| ratings.weight(p));
+
+        let config = &init.config;
+        let outputs = query_outputs(config);
+        let output = |ii: usize| outputs.get(ii).map(|o| o.name.as_str());
+
+        let mut monitors = Vec::new();
+        for (ii, timer) in timers.into_iter().enumerate() {
+            let mut own = own_collection(
+                &init,
+                &mut index,
+                &mut scanned,
+                &bans,
+                &Duplicates::default(),
+                ii,
+                output(ii),
+            );
+            if let Some(own) = own.as_mut() {
+                own.images_iter.set_weights(|p| ratings.weight(p));
+            }
+            let switch_timing = config.monitor_switch_timing(ii, output(ii), &collection);
+            let next_switch = next_switch_time(&switch_timing, timer.last_switch);
+            if next_switch.is_none() {
+                eprintln!(
+                    "Monitor {ii} will only change on request, its switch timing never matches"
+                );
+            }
+            monitors.push(Monitor {
+                state: match timer.running {
+                    true => State::Running,
+                    false => State::Stopped,
+                },
+                own,
+                switch_timing,
+                last_switch: timer.last_switch,
+                next_switch,
+            });
+        }
+
+        let fit = config.fit();
+        let watcher = watch_collections(config);
+        let converter = Converter::new(config.backend());
+
+        let mut engine = Engine {
+            watcher,
             init,
             images_iter: image_iter,
+            monitors,
+            outputs,
+            index,
+            scanned,
+            index_path,
+            duplicates: Duplicates::default(),
+            hasher: Hasher::new(),
+            fit,
+            converter,
             num_monitors,
-            state,
-            wallpaper_change_dur,
+            history,
+            history_path,
+            pins,
+            ratings,
+            ratings_path,
+            bans,
+            bans_path,
+            quarantine,
+            quarantine_path,
+            current,
+            snapshot_path,
+            collection,
+            active_rule,
+            showing_fallback: false,
+            reloading: None,
+        };
+        engine.check_images();
+        engine.check_duplicates();
+        engine.save_index();
+        engine
+    }
+
+    /// Switches the monitors that are running and due to switch
+    fn tick(&mut self) {
+        self.apply_changes();
+        self.check_schedule();
+        self.hash_images();
+        if self.showing_fallback && self.has_images() {
+            println!("Images found again, carrying on");
+            self.switch();
+            self.save_snapshot();
+            self.save_index();
+            return;
+        }
+        let now = SystemTime::now();
+        let due: Vec<usize> = (0..self.num_monitors)
+            .filter(|ii| self.monitors[*ii].is_due(now))
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+        for ii in due.iter() {
+            self.monitors[*ii].restart_timer();
+        }
+        self.next(&due);
+        self.save_snapshot();
+        self.save_index();
+    }
+
+    /// Shows the images that were on screen when the engine state was saved, if there were any
+    fn reapply(&mut self) {
+        if !self.current.is_empty() {
+            self.display(&self.current);
+        } else if !self.has_images() {
+            self.show_fallback();
+        }
+    }
+
+    /// Sets the background and remembers what is being shown
+    fn show(&mut self, images: Vec<PathBuf>) {
+        self.display(&images);
+        self.current = images;
+        self.showing_fallback = false;
+    }
+
+    /// Sets the background, converting images the backend can't show
+    fn display(&self, images: &[PathBuf]) {
+        let images: Vec<PathBuf> = images
+            .iter()
+            .map(|image| {
+                self.converter.displayable(image).unwrap_or_else(|e| {
+                    eprintln!("Can't show {}: {e}", image.display());
+                    image.clone()
+                })
+            })
+            .collect();
+        set_background(&images, self.fit);
+    }
+
+    /// If every monitor has images to pick from
+    fn has_images(&self) -> bool {
+        // Monitors with an empty collection of their own pick from the shared one
+        !self.images_iter.images().is_empty()
+            || self.monitors.iter().all(|m| {
+                m.own
+                    .as_ref()
+                    .is_some_and(|o| !o.images_iter.images().is_empty())
+            })
+    }
+
+    /// Shows the fallback from the config, if there is one, until there are images again
+    fn show_fallback(&mut self) {
+        if self.showing_fallback {
+            return;
+        }
+        eprintln!("No images to show, waiting for some to be added");
+        self.showing_fallback = true;
+        self.current.clear();
+        match self.init.config.fallback() {
+            Some(Fallback::Color { color }) => set_color(color),
+            Some(Fallback::Image { image }) => {
+                self.display(&vec![image.clone(); self.num_monitors])
+            }
+            None => {}
+        }
+    }
+
+    /// Shows the next images on `monitors`, going forward in the history of the monitors that had
+    /// gone back first. Returns false if there were no images to show.
+    fn next(&mut self, monitors: &[usize]) -> bool {
+        if self.current.len() != self.num_monitors {
+            return self.switch_monitors(monitors);
+        }
+        let mut fresh = Vec::new();
+        let mut images = self.current.clone();
+        for ii in monitors.iter().copied() {
+            let usable = |p: &Path| is_usable(p, &self.bans, &self.quarantine);
+            let forward = match self.pins[ii] {
+                Some(_) => None,
+                None => self.history.forward(ii, usable),
+            };
+            match forward {
+                Some(image) => images[ii] = image,
+                None => fresh.push(ii),
+            }
+        }
+        if fresh.len() < monitors.len() {
+            self.show(images);
+            self.save_history();
+        }
+        self.switch_monitors(&fresh)
+    }
+
+    /// Shows a new set of images, returns false if there were no images to show
+    fn switch(&mut self) -> bool {
+        let all: Vec<usize> = (0..self.num_monitors).collect();
+        self.switch_monitors(&all)
+    }
+
+    /// Shows new images on `monitors`, leaving the others as they are. Shows the fallback and
+    /// returns false if there were no images to show.
+    fn switch_monitors(&mut self, monitors: &[usize]) -> bool {
+        if monitors.is_empty() {
+            return true;
+        }
+        let Some(selected_images) = self.pick_images(monitors) else {
+            self.show_fallback();
+            return false;
+        };
+        self.history.push(&selected_images);
+        self.show(selected_images);
+        self.save_history();
+        true
+    }
+
+    /// Picks images for `monitors`, keeping what the other monitors show. Monitors that aren't
+    /// showing anything yet get an image too. Pinned images stay where they are and the same image
+    /// isn't shown on two monitors if there are enough images. `None` if a monitor is left with
+    /// nothing to show.
+    fn pick_images(&mut self, monitors: &[usize]) -> Option<Vec<PathBuf>> {
+        let monitors: Vec<usize> = (0..self.num_monitors)
+            .filter(|ii| monitors.contains(ii) || *ii >= self.current.len())
+            .collect();
+        let mut selected_images = self.current.clone();
+        selected_images.resize(self.num_monitors, PathBuf::new());
+
+        let mut exclude: Vec<PathBuf> = self.pins.iter().flatten().cloned().collect();
+        exclude.extend(
+            (0..self.num_monitors)
+                .filter(|ii| !monitors.contains(ii))
+                .map(|ii| selected_images[ii].clone()),
+        );
+        for ii in monitors.iter().copied() {
+            let image = match &self.pins[ii] {
+                Some(pinned) => Some(pinned.clone()),
+                None => self.next_image(ii, &exclude),
+            };
+            // Keep showing the old image if all the images have been removed, as long as it
+            // is still there
+            match image {
+                Some(image) => {
+                    exclude.push(image.clone());
+                    selected_images[ii] = image;
+                }
+                None if selected_images[ii].is_file() => {}
+                None => return None,
+            }
+        }
+        Some(selected_images)
+    }
+
+    /// The next image for `monitor` that can be shown, avoiding `exclude` if there are enough
+    /// images. `None` if there are no images left.
+    fn next_image(&mut self, monitor: usize, exclude: &[PathBuf]) -> Option<PathBuf> {
+        // Copies of the excluded images are the same image
+        let exclude = self.duplicates.with_copies(exclude);
+        loop {
+            let image = self.next_unchecked_image(monitor, &exclude)?;
+            let image = self.best_copy(monitor, &image);
+            if self.is_showable(&image, true) {
+                return Some(image);
+            }
+            self.save_quarantine();
+        }
+    }
+
+    /// The next image for `monitor`, avoiding `exclude` if there are enough images and preferring
+    /// images that suit the monitor if matching is set up. Monitors whose own collection has run
+    /// out of images use the shared collection. `None` if there are no images left.
+    fn next_unchecked_image(&mut self, monitor: usize, exclude: &[PathBuf]) -> Option<PathBuf> {
+        let matching = self.init.config.matching();
+        let size = self.outputs.get(monitor).map(|o| o.size);
+        let (Some(matching), Some(size)) = (matching, size) else {
+            if let Some(own) = &mut self.monitors[monitor].own {
+                if let Some(image) = own.images_iter.next_distinct(exclude) {
+                    return Some(image);
+                }
+            }
+            return self.images_iter.next_distinct(exclude);
+        };
+
+        let index = &mut self.index;
+        let duplicates = &self.duplicates;
+        // Images whose size isn't known are only shown if nothing else fits. Images with copies
+        // fit if any of the copies do.
+        let mut fits = |p: &Path| {
+            let mut fit = |p: &Path| {
+                // This runs for every image looked at, so the file is only read if its size
+                // isn't known yet. The image is checked properly before it is shown.
+                let info = match index.get(p).and_then(|f| f.info) {
+                    Some(info) => Some(info),
+                    None => index.check(p).ok(),
+                };
+                let dimensions = info.and_then(|info| info.dimensions);
+                dimensions.is_some_and(|d| matching.fits(d, size))
+            };
+            match duplicates.copies(p) {
+                [] => fit(p),
+                copies => copies.iter().any(|c| fit(c)),
+            }
+        };
+        if let Some(own) = &mut self.monitors[monitor].own {
+            if let Some(image) = own.images_iter.next_fitting(exclude, &mut fits) {
+                return Some(image);
+            }
         }
+        self.images_iter.next_fitting(exclude, fits)
     }
 
-    /// Runs the next wallpaper cycle of the engine, if it is running
-    fn cycle(&mut self) {
-        if matches!(self.state, State::Running) {
-            self.next();
+    /// The copy of `image` that suits `monitor` best, out of the copies that aren't quarantined
+    /// or banned
+    fn best_copy(&self, monitor: usize, image: &Path) -> PathBuf {
+        let size = self.outputs.get(monitor).map(|o| o.size);
+        self.duplicates.best_copy(image, size, |p| {
+            p.is_file() && !self.quarantine.contains(p) && !self.bans.is_banned(p)
+        })
+    }
+
+    /// Sets the state of `monitor`, or every monitor if it is `None`
+    fn set_state(&mut self, monitor: Option<usize>, state: State) -> ServerMessage {
+        match monitor {
+            Some(ii) if ii >= self.num_monitors => return ServerMessage::InvalidMonitor(ii),
+            Some(ii) => self.monitors[ii].state = state,
+            None => {
+                for m in self.monitors.iter_mut() {
+                    m.state = state.clone();
+                }
+            }
         }
+        ServerMessage::Ok
     }
 
-    /// Loads the next set of images
-    fn next(&mut self) {
-        let mut selected_images = Vec::new();
-        for _ in 0..self.num_monitors {
-            let image = self.images_iter.next().unwrap();
-            selected_images.push(image);
+    /// Goes back to the previous image in the history of `monitor`, or of every monitor if it is
+    /// `None`. Returns false if no monitor had one.
+    fn previous(&mut self, monitor: Option<usize>) -> bool {
+        if self.current.len() != self.num_monitors {
+            return false;
+        }
+        let mut images = self.current.clone();
+        let mut moved = false;
+        for (ii, image) in images.iter_mut().enumerate() {
+            if monitor.is_some_and(|m| m != ii) {
+                continue;
+            }
+            let usable = |p: &Path| is_usable(p, &self.bans, &self.quarantine);
+            if let Some(previous) = self.history.back(ii, usable) {
+                *image = previous;
+                moved = true;
+            }
+        }
+        if moved {
+            self.show(images);
+            self.save_history();
         }
-        set_background(&selected_images);
+        moved
     }
 
-    /// Handles a message from the client
-    fn handle_message(&mut self, msg: ClientMessage) {
+    /// Shows `path` on `monitor`, or every monitor if it is `None`, leaving the other monitors as
+    /// they are
+    fn set(&mut self, monitor: Option<usize>, path: PathBuf, pin: bool) -> ServerMessage {
+        if let Some(ii) = monitor {
+            if ii >= self.num_monitors {
+                return ServerMessage::InvalidMonitor(ii);
+            }
+        }
+        if !path.is_file() {
+            return ServerMessage::FileNotFound(path);
+        }
+        if !is_supported_image(&path, &self.init.config.extensions()) {
+            return ServerMessage::UnsupportedImage(path);
+        }
+
+        let mut selected_images = match self.current.len() == self.num_monitors {
+            true => self.current.clone(),
+            // The other monitors show the image too if there is nothing else to show
+            false => {
+                let all: Vec<usize> = (0..self.num_monitors).collect();
+                self.pick_images(&all)
+                    .unwrap_or_else(|| vec![path.clone(); self.num_monitors])
+            }
+        };
+        for (ii, (image, pinned)) in selected_images.iter_mut().zip(&mut self.pins).enumerate() {
+            if monitor.is_none_or(|m| m == ii) {
+                *image = path.clone();
+                if pin {
+                    *pinned = Some(path.clone());
+                }
+            }
+        }
+
+        self.history.push(&selected_images);
+        self.show(selected_images);
+        self.save_history();
+        ServerMessage::Ok
+    }
+
+    /// Removes the pin from `monitor`, or every monitor if it is `None`
+    fn unpin(&mut self, monitor: Option<usize>) -> ServerMessage {
+        match monitor {
+            Some(ii) if ii >= self.num_monitors => return ServerMessage::InvalidMonitor(ii),
+            Some(ii) => self.pins[ii] = None,
+            None => self.pins.fill(None),
+        }
+        ServerMessage::Ok
+    }
+
+    fn save_bans(&self) {
+        if let Some(path) = &self.bans_path {
+            if let Err(e) = self.bans.save(path) {
+                eprintln!("Failed to save bans to {}: {e}", path.display());
+            }
+        }
+    }
+
+    fn save_index(&mut self) {
+        if let Some(path) = &self.index_path {
+            if let Err(e) = self.index.save(path) {
+                eprintln!("Failed to save image index to {}: {e}", path.display());
+            }
+        }
+    }
+
+    fn save_quarantine(&self) {
+        if let Some(path) = &self.quarantine_path {
+            if let Err(e) = self.quarantine.save(path) {
+                eprintln!("Failed to save quarantine to {}: {e}", path.display());
+            }
+        }
+    }
+
+    fn save_snapshot(&self) {
+        let Some(path) = &self.snapshot_path else {
+            return;
+        };
+        let monitors: Vec<MonitorSnapshot> = self
+            .monitors
+            .iter()
+            .map(|m| MonitorSnapshot {
+                running: matches!(m.state, State::Running),
+                last_switch: m.last_switch,
+            })
+            .collect();
+        let snapshot = Snapshot {
+            running: monitors.iter().any(|m| m.running),
+            collection: self.collection.clone(),
+            rule: self.active_rule.as_ref().map(|r| r.description.clone()),
+            order: self.images_iter.order(),
+            images: self.images_iter.images().to_vec(),
+            position: self.images_iter.position(),
+            current: self.current.clone(),
+            pins: self.pins.clone(),
+            last_switch: monitors
+                .iter()
+                .map(|m| m.last_switch)
+                .max()
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            monitors,
+        };
+        if let Err(e) = snapshot.save(path) {
+            eprintln!("Failed to save engine state to {}: {e}", path.display());
+        }
+    }
+
+    fn save_history(&self) {
+        if let Some(path) = &self.history_path {
+            if let Err(e) = self.history.save(path) {
+                eprintln!("Failed to save history to {}: {e}", path.display());
+            }
+        }
+    }
+
+    /// Handles a request from the client, sending back the response unless it comes later
+    fn handle_request(&mut self, req: Request) {
+        let response = self.handle_message_inner(req.message, &req.reply);
+        self.save_snapshot();
+        self.save_index();
+        if let Some(response) = response {
+            // The client may have hung up already, there is nothing to do about that
+            let _ = req.reply.send(response);
+        }
+    }
+
+    fn handle_message_inner(
+        &mut self,
+        msg: ClientMessage,
+        reply: &Sender<ServerMessage>,
+    ) -> Option<ServerMessage> {
         match msg {
-            ClientMessage::Stop => {
-                self.state = State::Stopped;
+            ClientMessage::Stop { monitor } => {
+                return Some(self.set_state(monitor, State::Stopped));
+            }
+            ClientMessage::Start { monitor } => {
+                return Some(self.set_state(monitor, State::Running));
+            }
+            ClientMessage::Next { monitor: None } => {
+                let all: Vec<usize> = (0..self.num_monitors).collect();
+                if !self.next(&all) {
+                    return Some(ServerMessage::NoImagesFound);
+                }
+            }
+            ClientMessage::Next { monitor: Some(ii) } => {
+                if ii >= self.num_monitors {
+                    return Some(ServerMessage::InvalidMonitor(ii));
+                }
+                if !self.next(&[ii]) {
+                    return Some(ServerMessage::NoImagesFound);
+                }
+            }
+            ClientMessage::Previous { monitor } => {
+                if let Some(ii) = monitor.filter(|ii| *ii >= self.num_monitors) {
+                    return Some(ServerMessage::InvalidMonitor(ii));
+                }
+                if !self.previous(monitor) {
+                    return Some(ServerMessage::NoHistory);
+                }
+            }
+            ClientMessage::History => {
+                return Some(ServerMessage::History(self.history.monitors().to_vec()));
+            }
+            ClientMessage::Set { monitor, path, pin } => {
+                return Some(self.set(monitor, path, pin));
+            }
+            ClientMessage::Unpin { monitor } => {
+                return Some(self.unpin(monitor));
+            }
+            ClientMessage::Favourite { monitor } => {
+                return Some(self.rate(monitor, Some(MAX_RATING)));
+            }
+            ClientMessage::Rate { monitor, rating } => {
+                return Some(self.rate(monitor, Some(rating)));
+            }
+            ClientMessage::Unrate { monitor } => {
+                return Some(self.rate(monitor, None));
             }
-            ClientMessage::Start => {
-                self.state = State::Running;
+            ClientMessage::Ban { monitor } => {
+                return Some(self.ban(monitor));
             }
-            ClientMessage::Next => {
-                self.next();
+            ClientMessage::Bans => {
+                return Some(ServerMessage::Bans(self.bans.list().to_vec()));
             }
-            ClientMessage::Update(init) => {
-                let state = self.state.clone();
-                *self = Engine::new(init);
-                self.state = state;
+            ClientMessage::Unban(path) => {
+                return Some(self.unban(path));
+            }
+            ClientMessage::Status => {
+                let monitors: Vec<MonitorStatus> = self
+                    .monitors
+                    .iter()
+                    .map(|m| MonitorStatus {
+                        running: matches!(m.state, State::Running),
+                        collection: m.own.as_ref().map_or(&self.collection, |o| &o.name).clone(),
+                    })
+                    .collect();
+                return Some(ServerMessage::Status(Status {
+                    running: monitors.iter().any(|m| m.running),
+                    collection: self.collection.clone(),
+                    active_rule: self.active_rule.as_ref().map(|r| r.description.clone()),
+                    current: self.current.clone(),
+                    monitors,
+                    quarantined: self
+                        .quarantine
+                        .list()
+                        .iter()
+                        .rev()
+                        .take(MAX_STATUS_QUARANTINED)
+                        .cloned()
+                        .collect(),
+                    quarantined_count: self.quarantine.list().len(),
+                }));
+            }
+            ClientMessage::Collections => {
+                return Some(ServerMessage::Collections {
+                    names: self
+                        .init
+                        .config
+                        .collection_names()
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                    current: self.collection.clone(),
+                });
+            }
+            ClientMessage::UseCollection(name) => {
+                if !self.init.config.has_collection(&name) {
+                    return Some(ServerMessage::UnknownCollection(name));
+                }
+                if name != self.collection && !self.use_collection(name) {
+                    return Some(ServerMessage::NoImagesFound);
+                }
+            }
+            ClientMessage::Reload => {
+                // Answered once the images have been scanned
+                self.reload(reply.clone());
+                return None;
+            }
+            ClientMessage::Duplicates => {
+                return Some(self.duplicate_groups());
             }
         }
+        Some(ServerMessage::Ok)
     }
 }
 
-pub fn run(rx: Receiver<ClientMessage>, init: Init) -> ! {
-    let mut engine = Engine::new(init);
-    let mut start_time;
+pub fn run(rx: Receiver<Request>, loaded: Loaded) -> ! {
+    let mut engine = Engine::new(loaded);
     let message_poll_dur = Duration::from_millis(100);
 
+    engine.reapply();
     loop {
-        start_time = Instant::now();
+        engine.tick();
+        engine.check_reload();
+        if let Ok(req) = rx.recv_timeout(message_poll_dur) {
+            engine.handle_request(req);
+        }
+    }
+}
 
-        engine.cycle();
-        while start_time.elapsed() <= engine.wallpaper_change_dur {
-            if let Ok(msg) = rx.try_recv() {
-                engine.handle_message(msg);
-            }
-            std::thread::sleep(message_poll_dur);
+/// The monitors' outputs, if the config needs their names or sizes
+fn query_outputs(config: &Config) -> Vec<Output> {
+    let sizes = config.matching().is_some() || config.duplicates().enabled;
+    match config.has_monitor_outputs() || sizes {
+        true => monitors::outputs(),
+        false => Vec::new(),
+    }
+}
+
+/// The path a file is saved to, or `None` with a warning that `what` won't be saved if its
+/// directory isn't available
+fn saved_file(path: Result<PathBuf, SowmError>, what: &str) -> Option<PathBuf> {
+    match path {
+        Ok(p) => Some(p),
+        Err(e) => {
+            eprintln!("{what} won't be saved: {e}");
+            None
         }
     }
 }
 
+/// If an image in the history can still be shown
+fn is_usable(path: &Path, bans: &Bans, quarantine: &Quarantine) -> bool {
+    path.is_file() && !quarantine.contains(path) && !bans.is_banned(path)
+}
+
+/// Fills the background with a solid colour
+fn set_color(color: &str) {
+    let status = std::process::Command::new("xsetroot")
+        .arg("-solid")
+        .arg(color)
+        .status();
+    if let Err(e) = status {
+        eprintln!("Failed to run xsetroot to show the fallback colour: {e}");
+    }
+}
+
 /// Sets the background to the list of images. There should be as many images as there is monitors
-fn set_background<P>(selected_images: &[P])
+fn set_background<P>(selected_images: &[P], fit: Fit)
 where
     P: AsRef<Path>,
 {
     // Example: feh --no-fehbg --bg-fill image1.jpg image2.jpeg
 
-    // TODO: Check feh exists before entering this method
     let mut cmd = std::process::Command::new("feh");
-    cmd.arg("--no-fehbg").arg("--bg-fill");
+    cmd.arg("--no-fehbg").arg(format!("--bg-{fit}"));
     for image in selected_images.iter() {
         cmd.arg(image.as_ref());
     }
-    // TODO: handle feh not found error
-    cmd.spawn().unwrap();
+    match cmd.spawn() {
+        // Wait elsewhere so the engine doesn't block on feh, and feh isn't left as a zombie
+        Ok(mut child) => {
+            std::thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => {
+                    eprintln!("feh failed to set the background: {status}")
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to wait for feh: {e}"),
+            });
+        }
+        Err(e) => eprintln!("Failed to run feh to set the background: {e}"),
+    }
 }
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use sowm_common::ServerMessage;
+
+use super::Engine;
+
+impl Engine {
+    /// Bans the image currently shown on `monitor` and replaces it with the next image
+    pub(super) fn ban(&mut self, monitor: usize) -> ServerMessage {
+        if monitor >= self.num_monitors {
+            return ServerMessage::InvalidMonitor(monitor);
+        }
+        let mut selected_images = self.current.clone();
+        let Some(path) = selected_images.get(monitor).cloned() else {
+            return ServerMessage::NoCurrentImage;
+        };
+
+        let banned = match self.bans.ban(path.clone()) {
+            Ok(banned) => banned.clone(),
+            Err(e) => return ServerMessage::IoError(format!("{}: {e}", path.display())),
+        };
+        self.save_bans();
+        self.scanned.remove(&path);
+        self.images_iter.remove(&path);
+        for own in self.monitors.iter_mut().filter_map(|m| m.own.as_mut()) {
+            own.images_iter.remove(&path);
+        }
+        for pin in self.pins.iter_mut() {
+            if pin.as_ref() == Some(&path) {
+                *pin = None;
+            }
+        }
+
+        // Only the banned image is replaced, the other monitors keep what they are showing
+        for ii in 0..selected_images.len() {
+            if selected_images[ii] != path {
+                continue;
+            }
+            match self.next_image(ii, &selected_images) {
+                Some(image) => selected_images[ii] = image,
+                None => {
+                    self.show_fallback();
+                    return ServerMessage::Banned(banned);
+                }
+            }
+        }
+        self.history.push(&selected_images);
+        self.show(selected_images);
+        self.save_history();
+
+        ServerMessage::Banned(banned)
+    }
+
+    /// Lets a banned image be shown again, adding it back to the collections it is in
+    pub(super) fn unban(&mut self, path: PathBuf) -> ServerMessage {
+        if !self.bans.remove(&path) {
+            return ServerMessage::NotBanned(path);
+        }
+        self.save_bans();
+        // Bring it back without waiting for a reload
+        if path.is_file() {
+            self.add_image(path);
+            self.update_weights();
+        }
+        ServerMessage::Ok
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::Timelike;
+use sowm_common::{
+    bans::Bans,
+    get_images_until,
+    index::Index,
+    scan::ScanDir,
+    schedule::{active_date_rule, active_rule, TimeOfDay},
+    Config, Init, DEFAULT_COLLECTION,
+};
+
+use super::Engine;
+use crate::{dupes::Duplicates, looping_iter::LoopingIter, solar, watcher::DirWatcher};
+
+/// A rule from the config that picked which collection to show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ActiveRule {
+    pub(super) collection: String,
+    /// Shown to the user so they can tell which rule is active
+    pub(super) description: String,
+}
+
+impl ActiveRule {
+    /// Finds the rule that applies right now. Date rules take priority over time of day
+    /// schedules, which take priority over the solar schedule.
+    pub(super) fn now(config: &Config) -> Option<Self> {
+        if let Some(rule) = active_date_rule(config.dates(), chrono::Local::now().date_naive()) {
+            return Some(ActiveRule {
+                collection: rule.collection.clone(),
+                description: rule.to_string(),
+            });
+        }
+
+        if let Some(rule) = active_rule(config.schedule(), local_time_of_day()) {
+            return Some(ActiveRule {
+                collection: rule.collection.clone(),
+                description: rule.to_string(),
+            });
+        }
+
+        let solar = config.solar()?;
+        let phase = solar::phase(chrono::Utc::now(), solar.latitude, solar.longitude);
+        let collection = match phase {
+            solar::Phase::Day => &solar.day,
+            solar::Phase::Dusk => &solar.dusk,
+            solar::Phase::Night => &solar.night,
+        };
+        Some(ActiveRule {
+            collection: collection.clone(),
+            description: format!("solar {phase} ({collection})"),
+        })
+    }
+}
+
+/// A collection that one monitor always shows
+pub(super) struct OwnCollection {
+    pub(super) name: String,
+    pub(super) images_iter: LoopingIter,
+}
+
+/// Images of each collection that has been scanned, copies included. They are kept up to date
+/// with the changes the watcher sees, so collections are only scanned again on a reload.
+#[derive(Default)]
+pub(super) struct Scanned {
+    collections: HashMap<String, HashSet<PathBuf>>,
+}
+
+impl Scanned {
+    /// Images of the collection called `name`, scanning it if it hasn't been yet
+    pub(super) fn get(
+        &mut self,
+        init: &Init,
+        index: &mut Index,
+        name: &str,
+        bans: &Bans,
+    ) -> Vec<PathBuf> {
+        if !self.collections.contains_key(name) {
+            self.scan(&init.config, index, &[name], bans, &|| false)
+                .expect("Scans that aren't cancelled finish");
+        }
+        self.collections[name].iter().cloned().collect()
+    }
+
+    /// Scans the collections called `names` that haven't been scanned yet. Gives up once `cancel`
+    /// returns true, giving `None`.
+    pub(super) fn scan(
+        &mut self,
+        config: &Config,
+        index: &mut Index,
+        names: &[&str],
+        bans: &Bans,
+        cancel: &dyn Fn() -> bool,
+    ) -> Option<()> {
+        for name in names {
+            if self.collections.contains_key(*name) {
+                continue;
+            }
+            let dirs = config.collection_dirs(name).unwrap_or_default();
+            let images = get_images_until(&dirs, bans, index, cancel)?;
+            self.collections
+                .insert(name.to_string(), images.into_iter().collect());
+        }
+        Some(())
+    }
+
+    /// Images of the collection called `name`, `None` if it hasn't been scanned
+    pub(super) fn images(&self, name: &str) -> Option<&HashSet<PathBuf>> {
+        self.collections.get(name)
+    }
+
+    /// Adds a new image to the collections `in_collection` says it is in
+    pub(super) fn insert<F>(&mut self, path: &Path, in_collection: F)
+    where
+        F: Fn(&str) -> bool,
+    {
+        for (name, images) in self.collections.iter_mut() {
+            if in_collection(name) {
+                images.insert(path.to_path_buf());
+            }
+        }
+    }
+
+    /// Removes the image at `path`, or every image under it if it was a directory
+    pub(super) fn remove(&mut self, path: &Path) {
+        for images in self.collections.values_mut() {
+            images.retain(|p| !p.starts_with(path));
+        }
+    }
+}
+
+impl Engine {
+    /// Switches collection if a different schedule rule applies now
+    pub(super) fn check_schedule(&mut self) {
+        let rule = ActiveRule::now(&self.init.config);
+        if rule == self.active_rule {
+            return;
+        }
+        let collection = rule
+            .as_ref()
+            .map_or(DEFAULT_COLLECTION, |r| r.collection.as_str())
+            .to_string();
+        self.active_rule = rule;
+        if collection != self.collection {
+            self.use_collection(collection);
+        }
+    }
+
+    /// Starts picking images from the collection called `name` and shows them straight away,
+    /// returns false if there are no images in it
+    pub(super) fn use_collection(&mut self, name: String) -> bool {
+        let images = self
+            .scanned
+            .get(&self.init, &mut self.index, &name, &self.bans);
+        let images = self.duplicates.dedupe(images);
+        if images.is_empty() {
+            eprintln!(
+                "No images in collection {name}, staying on {}",
+                self.collection
+            );
+            return false;
+        }
+        println!("Switching to collection {name}");
+        let config = &self.init.config;
+        self.images_iter =
+            LoopingIter::new(images, config.collection_order(&name), self.num_monitors);
+        self.images_iter.set_weights(|p| self.ratings.weight(p));
+
+        let shared = self.shared_monitors();
+        for ii in 0..self.num_monitors {
+            let output = self.outputs.get(ii).map(|o| o.name.as_str());
+            let monitor = &mut self.monitors[ii];
+            monitor.switch_timing = config.monitor_switch_timing(ii, output, &name);
+            if shared.contains(&ii) {
+                monitor.restart_timer();
+            }
+        }
+        self.collection = name;
+        self.check_images();
+        self.check_duplicates();
+        self.switch_monitors(&shared);
+        true
+    }
+
+    /// Names of the collections in use, the shared one first
+    pub(super) fn collections_in_use(&self) -> Vec<String> {
+        let mut names = vec![self.collection.clone()];
+        for own in self.monitors.iter().filter_map(|m| m.own.as_ref()) {
+            if !names.contains(&own.name) {
+                names.push(own.name.clone());
+            }
+        }
+        names
+    }
+
+    /// Monitors showing the shared collection rather than their own
+    fn shared_monitors(&self) -> Vec<usize> {
+        (0..self.num_monitors)
+            .filter(|ii| self.monitors[*ii].own.is_none())
+            .collect()
+    }
+}
+
+/// The collection that the monitor at `index` always shows, if it has one with images in it
+pub(super) fn own_collection(
+    init: &Init,
+    image_index: &mut Index,
+    scanned: &mut Scanned,
+    bans: &Bans,
+    duplicates: &Duplicates,
+    index: usize,
+    output: Option<&str>,
+) -> Option<OwnCollection> {
+    let config = &init.config;
+    let name = config.monitor(index, output)?.collection.clone()?;
+    let images = duplicates.dedupe(scanned.get(init, image_index, &name, bans));
+    if images.is_empty() {
+        eprintln!("No images in collection {name}, monitor {index} will use the shared collection");
+        return None;
+    }
+    let images_iter = LoopingIter::new(images, config.collection_order(&name), 1);
+    Some(OwnCollection { name, images_iter })
+}
+
+/// Directories of all the collections, without duplicates
+pub(super) fn watched_dirs(config: &Config) -> Vec<ScanDir> {
+    let mut dirs: Vec<ScanDir> = Vec::new();
+    for dir in config
+        .collection_names()
+        .into_iter()
+        .flat_map(|name| config.collection_dirs(name).unwrap_or_default())
+    {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Starts watching the directories of all the collections
+pub(super) fn watch_collections(config: &Config) -> Option<DirWatcher> {
+    match DirWatcher::new(watched_dirs(config)) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            eprintln!("Image directories won't be watched: {e}");
+            None
+        }
+    }
+}
+
+/// The current local time of day
+fn local_time_of_day() -> TimeOfDay {
+    let now = chrono::Local::now();
+    TimeOfDay::new(now.hour() as u16, now.minute() as u16)
+        .expect("chrono should always give a valid time of day")
+}
@@ -0,0 +1,124 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use sowm_common::{DuplicateImage, ServerMessage, Validation};
+
+use super::Engine;
+use crate::dupes::{Duplicates, Hashes};
+
+impl Engine {
+    /// Every image in the collections in use, copies included
+    fn known_images(&mut self) -> Vec<PathBuf> {
+        let mut images = HashSet::new();
+        for name in self.collections_in_use() {
+            images.extend(
+                self.scanned
+                    .get(&self.init, &mut self.index, &name, &self.bans),
+            );
+        }
+        images.into_iter().collect()
+    }
+
+    /// Starts looking for copies in the collections in use. Images that haven't been hashed yet
+    /// are sent to be hashed on another thread, and the copies are found once they all have been.
+    pub(super) fn check_duplicates(&mut self) {
+        if !self.init.config.duplicates().enabled {
+            self.hasher.forget();
+            if !self.duplicates.groups().is_empty() {
+                // The copies that were left out can be shown again
+                self.duplicates = Duplicates::default();
+                self.sync_collections();
+            }
+            return;
+        }
+        for path in self.known_images() {
+            self.hasher.queue(&self.index, &path);
+        }
+        if self.hasher.pending() == 0 {
+            self.find_duplicates();
+        }
+    }
+
+    /// Records the hashes worked out since the last tick, and finds the copies once every image
+    /// has been hashed
+    pub(super) fn hash_images(&mut self) {
+        if !self.hasher.receive(&mut self.index) {
+            return;
+        }
+        if self.hasher.pending() == 0 && self.init.config.duplicates().enabled {
+            self.find_duplicates();
+            self.save_index();
+        }
+    }
+
+    /// Groups the images in the collections in use that are copies of each other and keeps only
+    /// one image of each group in the collections
+    pub(super) fn find_duplicates(&mut self) {
+        let images: Vec<(PathBuf, Hashes)> = self
+            .known_images()
+            .into_iter()
+            .map(|path| {
+                let hashes = self
+                    .index
+                    .get(&path)
+                    .map_or_else(Hashes::default, |file| Hashes {
+                        content: file.hash,
+                        perceptual: file.perceptual,
+                        dimensions: file.info.and_then(|info| info.dimensions),
+                    });
+                (path, hashes)
+            })
+            .collect();
+        let max_distance = self.init.config.duplicates().max_distance;
+        let duplicates = Duplicates::find(images, max_distance);
+        let copies: usize = duplicates.groups().iter().map(|g| g.len() - 1).sum();
+        if copies > 0 {
+            println!("Found {copies} copies of other images, only one of each will be shown");
+        }
+        self.duplicates = duplicates;
+        self.sync_collections();
+    }
+
+    /// Updates the images of the collections in use from the scanned images, keeping one image of
+    /// each group of copies and leaving out the quarantined ones
+    fn sync_collections(&mut self) {
+        let validation = self.init.config.validation();
+        let quarantine = &self.quarantine;
+        let usable = |images: Vec<PathBuf>| -> Vec<PathBuf> {
+            images
+                .into_iter()
+                .filter(|p| validation == Validation::Off || !quarantine.contains(p))
+                .collect()
+        };
+        let (init, index, bans) = (&self.init, &mut self.index, &self.bans);
+        let images = self.scanned.get(init, index, &self.collection, bans);
+        self.images_iter
+            .sync(self.duplicates.dedupe(usable(images)));
+        for own in self.monitors.iter_mut().filter_map(|m| m.own.as_mut()) {
+            let images = self.scanned.get(init, index, &own.name, bans);
+            own.images_iter.sync(self.duplicates.dedupe(usable(images)));
+        }
+        self.update_weights();
+    }
+
+    /// The groups of images that are copies of each other
+    pub(super) fn duplicate_groups(&self) -> ServerMessage {
+        let groups = self
+            .duplicates
+            .groups()
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|path| DuplicateImage {
+                        path: path.clone(),
+                        dimensions: self.duplicates.dimensions(path),
+                    })
+                    .collect()
+            })
+            .collect();
+        ServerMessage::Duplicates {
+            groups,
+            unchecked: self.hasher.pending(),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use sowm_common::Validation;
+
+use super::Engine;
+use crate::watcher::Change;
+
+impl Engine {
+    /// Adds and removes images that have changed in the watched directories
+    pub(super) fn apply_changes(&mut self) {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        let changes = watcher.changes();
+        if changes.is_empty() {
+            return;
+        }
+        // Only removing images that have copies changes the groups, new images are grouped once
+        // they have been hashed
+        let mut regroup = false;
+        for change in changes {
+            match change {
+                Change::Added(path) => self.add_image(path),
+                Change::Removed(path) => {
+                    let groups = self.duplicates.groups();
+                    regroup |= groups.iter().flatten().any(|p| p.starts_with(&path));
+                    self.remove_images(&path);
+                }
+            }
+        }
+        self.update_weights();
+        if regroup && self.hasher.pending() == 0 {
+            self.find_duplicates();
+        }
+    }
+
+    /// Adds a new image to every collection it belongs to
+    pub(super) fn add_image(&mut self, path: PathBuf) {
+        if self.bans.is_banned(&path) {
+            return;
+        }
+        self.index.add_file(&path);
+        let scan = self.init.config.validation() == Validation::Scan;
+        if !self.is_showable(&path, scan) {
+            self.save_quarantine();
+            return;
+        }
+        let config = &self.init.config;
+        let in_collection = |name: &str| {
+            config
+                .collection_dirs(name)
+                .is_some_and(|dirs| dirs.iter().any(|d| d.options.is_image(&d.path, &path)))
+        };
+        if in_collection(&self.collection) {
+            self.images_iter.insert(path.clone());
+        }
+        for own in self.monitors.iter_mut().filter_map(|m| m.own.as_mut()) {
+            if in_collection(&own.name) {
+                own.images_iter.insert(path.clone());
+            }
+        }
+        self.scanned.insert(&path, in_collection);
+        if config.duplicates().enabled {
+            self.hasher.queue(&self.index, &path);
+        }
+    }
+
+    /// Removes the image at `path`, or every image under it if it was a directory
+    fn remove_images(&mut self, path: &Path) {
+        self.scanned.remove(path);
+        self.images_iter.remove(path);
+        for own in self.monitors.iter_mut().filter_map(|m| m.own.as_mut()) {
+            own.images_iter.remove(path);
+        }
+        for pin in self.pins.iter_mut() {
+            if pin.as_ref().is_some_and(|p| p.starts_with(path)) {
+                *pin = None;
+            }
+        }
+    }
+
+    /// Takes images that can't be shown out of the collections. The images are only read if they
+    /// are validated on scanning, otherwise just the ones already in quarantine are taken out.
+    pub(super) fn check_images(&mut self) {
+        let validation = self.init.config.validation();
+        if validation == Validation::Off {
+            return;
+        }
+        let mut changed = self.quarantine.prune();
+        let mut images: HashSet<PathBuf> = self.images_iter.images().iter().cloned().collect();
+        for own in self.monitors.iter().filter_map(|m| m.own.as_ref()) {
+            images.extend(own.images_iter.images().iter().cloned());
+        }
+        for image in images {
+            if !self.is_showable(&image, validation == Validation::Scan) {
+                changed = true;
+            }
+        }
+        if changed {
+            self.save_quarantine();
+        }
+    }
+
+    /// If the image at `path` can be shown. Images that can't be are quarantined and taken out of
+    /// the collections. The file is only read if `read` is true, otherwise only the quarantine is
+    /// looked at.
+    pub(super) fn is_showable(&mut self, path: &Path, read: bool) -> bool {
+        if self.init.config.validation() == Validation::Off {
+            return true;
+        }
+        if !self.quarantine.contains(path) {
+            if !read {
+                return true;
+            }
+            // Images are converted now so they don't fail when it is their turn
+            let checked = self
+                .index
+                .check(path)
+                .and_then(|_| self.converter.displayable(path));
+            let Err(reason) = checked else {
+                return true;
+            };
+            eprintln!("Quarantining {}: {reason}", path.display());
+            self.quarantine.add(path.to_path_buf(), reason);
+        }
+        self.remove_images(path);
+        false
+    }
+}
@@ -0,0 +1,42 @@
+use sowm_common::ServerMessage;
+
+use super::Engine;
+
+impl Engine {
+    /// Sets the rating of the image currently shown on `monitor`, or removes it if `rating` is
+    /// `None`
+    pub(super) fn rate(&mut self, monitor: usize, rating: Option<u8>) -> ServerMessage {
+        if monitor >= self.num_monitors {
+            return ServerMessage::InvalidMonitor(monitor);
+        }
+        let Some(path) = self.current.get(monitor).cloned() else {
+            return ServerMessage::NoCurrentImage;
+        };
+
+        match rating {
+            Some(rating) => {
+                if !self.ratings.rate(path.clone(), rating) {
+                    return ServerMessage::InvalidRating(rating);
+                }
+            }
+            None => self.ratings.unrate(&path),
+        }
+        self.update_weights();
+
+        if let Some(ratings_path) = &self.ratings_path {
+            if let Err(e) = self.ratings.save(ratings_path) {
+                eprintln!("Failed to save ratings to {}: {e}", ratings_path.display());
+            }
+        }
+        ServerMessage::Rated { path, rating }
+    }
+
+    /// Sets the weights of every iterator from the ratings
+    pub(super) fn update_weights(&mut self) {
+        let ratings = &self.ratings;
+        self.images_iter.set_weights(|p| ratings.weight(p));
+        for own in self.monitors.iter_mut().filter_map(|m| m.own.as_mut()) {
+            own.images_iter.set_weights(|p| ratings.weight(p));
+        }
+    }
+}
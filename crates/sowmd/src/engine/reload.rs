@@ -0,0 +1,327 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    time::SystemTime,
+};
+
+use sowm_common::{
+    bans::{Bans, BANS_FILE},
+    config_modified, data_file, init, Init, ReloadSummary, ServerMessage, SowmError,
+    DEFAULT_COLLECTION,
+};
+
+use super::{
+    collection::{own_collection, watch_collections, watched_dirs, Scanned},
+    query_outputs, Engine, Monitor, State,
+};
+use crate::{convert::Converter, looping_iter::LoopingIter, timer::next_switch_time};
+
+/// A config and the images of the collections that were scanned for it
+pub struct Loaded {
+    pub(super) init: Init,
+    pub(super) scanned: Scanned,
+}
+
+impl Loaded {
+    pub fn init(&self) -> &Init {
+        &self.init
+    }
+}
+
+/// Reads the config and scans the default collection and the collections called `names`
+pub fn load(names: &[String]) -> Result<Loaded, SowmError> {
+    loop {
+        let mut init = init()?;
+        let bans = data_file(BANS_FILE).map(Bans::load).unwrap_or_default();
+        let names: Vec<&str> = std::iter::once(DEFAULT_COLLECTION)
+            .chain(names.iter().map(|n| n.as_str()))
+            .filter(|n| init.config.has_collection(n))
+            .collect();
+        // A scan of a big library can take a while, so start again with the new config if it
+        // changes in the meantime. What was scanned so far is kept in the index.
+        let (path, modified) = (init.config_path.clone(), init.config_modified);
+        let changed = || config_modified(&path) != modified;
+        let mut scanned = Scanned::default();
+        if scanned
+            .scan(&init.config, &mut init.index, &names, &bans, &changed)
+            .is_none()
+        {
+            println!("The config changed while scanning, starting again");
+            continue;
+        }
+        return Ok(Loaded { init, scanned });
+    }
+}
+
+/// A reload whose config is being read and images scanned on another thread
+pub(super) struct Reloading {
+    loaded: Receiver<Result<Loaded, SowmError>>,
+    /// Clients waiting for the reload to be applied
+    replies: Vec<Sender<ServerMessage>>,
+    /// Clients that asked to reload again while the scan was running, the config may have changed
+    /// after it was read
+    again: Vec<Sender<ServerMessage>>,
+}
+
+impl Engine {
+    /// Starts reading the config and scanning the images again on another thread, so the
+    /// wallpaper keeps switching during a long scan. `reply` gets the summary once the reload is
+    /// applied.
+    pub(super) fn reload(&mut self, reply: Sender<ServerMessage>) {
+        if let Some(reloading) = self.reloading.as_mut() {
+            reloading.again.push(reply);
+            return;
+        }
+        // The scan carries on from the saved index
+        self.save_index();
+        let names = self.collections_in_use();
+        let (tx, loaded) = channel();
+        std::thread::spawn(move || {
+            // The engine may have stopped, there is nothing to do about that
+            let _ = tx.send(load(&names));
+        });
+        self.reloading = Some(Reloading {
+            loaded,
+            replies: vec![reply],
+            again: Vec::new(),
+        });
+    }
+
+    /// Applies the reload if its scan has finished, answering the clients waiting for it
+    pub(super) fn check_reload(&mut self) {
+        let Some(reloading) = self.reloading.take() else {
+            return;
+        };
+        let result = match reloading.loaded.try_recv() {
+            Ok(Ok(loaded)) => Ok(self.apply_reload(loaded)),
+            Ok(Err(e)) => {
+                eprintln!("Failed to reload, keeping the old config: {e}");
+                Err(e.to_string())
+            }
+            Err(TryRecvError::Empty) => {
+                self.reloading = Some(reloading);
+                return;
+            }
+            Err(TryRecvError::Disconnected) => {
+                eprintln!("The reload stopped before it finished, keeping the old config");
+                Err("the reload stopped before it finished".to_string())
+            }
+        };
+        self.save_snapshot();
+        self.save_index();
+        for reply in reloading.replies {
+            let response = match &result {
+                Ok(summary) => ServerMessage::Reloaded(*summary),
+                Err(e) => ServerMessage::InvalidConfig(e.clone()),
+            };
+            // The client may have hung up already, there is nothing to do about that
+            let _ = reply.send(response);
+        }
+        for reply in reloading.again {
+            self.reload(reply);
+        }
+    }
+
+    /// Switches to the reloaded config and images, keeping which monitors are running and pinned
+    fn apply_reload(&mut self, loaded: Loaded) -> ReloadSummary {
+        let empty = HashSet::new();
+        let old = self.scanned.images(DEFAULT_COLLECTION).unwrap_or(&empty);
+        let new = loaded.scanned.images(DEFAULT_COLLECTION).unwrap_or(&empty);
+        let summary = ReloadSummary {
+            config_changed: loaded.init.config != self.init.config,
+            images_added: new.difference(old).count(),
+            images_removed: old.difference(new).count(),
+        };
+        println!("Reloaded: {summary}");
+
+        self.apply(loaded);
+        summary
+    }
+
+    /// Adds or removes monitors at the end so there are `num_monitors`, keeping what the others
+    /// are doing. Added monitors run and switch on the next tick.
+    fn resize_monitors(&mut self, num_monitors: usize) {
+        if num_monitors == self.num_monitors {
+            return;
+        }
+        let config = &self.init.config;
+        resize_monitors(
+            &mut self.monitors,
+            &mut self.pins,
+            &mut self.current,
+            num_monitors,
+            |ii| Monitor {
+                state: State::Running,
+                own: None,
+                switch_timing: config.monitor_switch_timing(ii, None, &self.collection),
+                last_switch: SystemTime::UNIX_EPOCH,
+                next_switch: Some(SystemTime::UNIX_EPOCH),
+            },
+        );
+        let removed = num_monitors < self.num_monitors;
+        self.num_monitors = num_monitors;
+        self.images_iter.set_recent(num_monitors);
+        // The images of removed monitors shouldn't be passed to the backend anymore
+        if removed && !self.current.is_empty() && !self.showing_fallback {
+            self.display(&self.current);
+        }
+    }
+
+    /// Switches to a new config and set of images, only changing what is different so the images
+    /// stay in the order they were in
+    fn apply(&mut self, loaded: Loaded) {
+        let Loaded { mut init, scanned } = loaded;
+        self.index = std::mem::take(&mut init.index);
+        // The images were scanned again for the reload
+        self.scanned = scanned;
+        let old = std::mem::replace(&mut self.init, init);
+        self.resize_monitors(self.init.config.num_monitors());
+        let (old_config, config) = (&old.config, &self.init.config);
+        let outputs = query_outputs(config);
+        let output = |ii: usize| outputs.get(ii).map(|o| o.name.as_str());
+        // If the images of a collection can be updated in place
+        let same_collection = |name: &str| {
+            old_config.collection_dirs(name) == config.collection_dirs(name)
+                && old_config.collection_order(name) == config.collection_order(name)
+        };
+
+        let name = self.collection.clone();
+        let collection_removed = !config.has_collection(&name);
+        if !collection_removed {
+            let images = self
+                .scanned
+                .get(&self.init, &mut self.index, &name, &self.bans);
+            let images = self.duplicates.dedupe(images);
+            if same_collection(&name) {
+                self.images_iter.sync(images);
+            } else {
+                let order = config.collection_order(&name);
+                let mut images_iter = LoopingIter::new(images, order, self.num_monitors);
+                if order == self.images_iter.order() {
+                    images_iter.restore(self.images_iter.images(), self.images_iter.position());
+                }
+                self.images_iter = images_iter;
+            }
+        }
+
+        for (ii, monitor) in self.monitors.iter_mut().enumerate() {
+            let wanted = config
+                .monitor(ii, output(ii))
+                .and_then(|m| m.collection.as_deref());
+            match monitor.own.as_mut() {
+                Some(own) if Some(own.name.as_str()) == wanted && same_collection(&own.name) => {
+                    let images =
+                        self.scanned
+                            .get(&self.init, &mut self.index, &own.name, &self.bans);
+                    own.images_iter.sync(self.duplicates.dedupe(images));
+                }
+                _ => {
+                    monitor.own = own_collection(
+                        &self.init,
+                        &mut self.index,
+                        &mut self.scanned,
+                        &self.bans,
+                        &self.duplicates,
+                        ii,
+                        output(ii),
+                    )
+                }
+            }
+
+            let switch_timing = config.monitor_switch_timing(ii, output(ii), &name);
+            if switch_timing != monitor.switch_timing {
+                // Carry on from the last switch rather than starting the wait again
+                monitor.next_switch = next_switch_time(&switch_timing, monitor.last_switch);
+                monitor.switch_timing = switch_timing;
+            }
+        }
+
+        self.fit = config.fit();
+        if watched_dirs(old_config) != watched_dirs(config) {
+            self.watcher = watch_collections(config);
+        }
+        if config.backend() != self.converter.backend() {
+            self.converter = Converter::new(config.backend());
+        }
+        self.outputs = outputs;
+        self.update_weights();
+        self.check_images();
+        self.check_duplicates();
+
+        if collection_removed {
+            eprintln!("Collection {name} was removed");
+            self.use_collection(DEFAULT_COLLECTION.to_string());
+        }
+        // The schedule rules may have changed
+        self.check_schedule();
+    }
+}
+
+/// Adds monitors made by `new_monitor` or removes them from the end so there are `num_monitors`,
+/// along with their pins and current images. Added monitors have no current image until the next
+/// switch.
+fn resize_monitors<F>(
+    monitors: &mut Vec<Monitor>,
+    pins: &mut Vec<Option<PathBuf>>,
+    current: &mut Vec<PathBuf>,
+    num_monitors: usize,
+    new_monitor: F,
+) where
+    F: Fn(usize) -> Monitor,
+{
+    monitors.truncate(num_monitors);
+    while monitors.len() < num_monitors {
+        monitors.push(new_monitor(monitors.len()));
+    }
+    pins.resize(num_monitors, None);
+    current.truncate(num_monitors);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use sowm_common::SwitchTiming;
+
+    use super::*;
+
+    fn monitor(state: State) -> Monitor {
+        Monitor {
+            state,
+            own: None,
+            switch_timing: SwitchTiming::Interval(Duration::from_secs(60)),
+            last_switch: SystemTime::UNIX_EPOCH,
+            next_switch: None,
+        }
+    }
+
+    fn running(monitors: &[Monitor]) -> Vec<bool> {
+        monitors
+            .iter()
+            .map(|m| matches!(m.state, State::Running))
+            .collect()
+    }
+
+    #[test]
+    fn changing_monitor_count_keeps_the_others() {
+        let mut monitors = vec![monitor(State::Stopped), monitor(State::Running)];
+        let mut pins = vec![Some(PathBuf::from("a")), None];
+        let mut current = vec![PathBuf::from("a"), PathBuf::from("b")];
+
+        resize_monitors(&mut monitors, &mut pins, &mut current, 3, |_| {
+            monitor(State::Running)
+        });
+        assert_eq!(running(&monitors), [false, true, true]);
+        assert_eq!(pins, [Some(PathBuf::from("a")), None, None]);
+        // The added monitor gets an image on the next switch
+        assert_eq!(current, [PathBuf::from("a"), PathBuf::from("b")]);
+
+        resize_monitors(&mut monitors, &mut pins, &mut current, 1, |_| {
+            monitor(State::Running)
+        });
+        assert_eq!(running(&monitors), [false]);
+        assert_eq!(pins, [Some(PathBuf::from("a"))]);
+        assert_eq!(current, [PathBuf::from("a")]);
+    }
+}
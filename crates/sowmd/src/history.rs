@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sowm_common::MonitorHistory;
+
+/// Maximum number of images kept in the history of each monitor
+const MAX_HISTORY: usize = 100;
+
+/// A bounded history of the images each monitor has shown, with a cursor for each monitor that
+/// can be moved back and forward through its history
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    monitors: Vec<MonitorHistory>,
+}
+
+impl History {
+    /// Loads the history of `num_monitors` monitors from the file at `path`, returning an empty
+    /// history if it can't be read
+    pub fn load<P>(path: P, num_monitors: usize) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let mut history: History = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        history.monitors.resize_with(num_monitors, Default::default);
+
+        // Guard against a hand edited file putting a cursor out of bounds
+        for monitor in history.monitors.iter_mut() {
+            if monitor.position >= monitor.entries.len() {
+                monitor.position = monitor.entries.len().saturating_sub(1);
+            }
+        }
+        history
+    }
+
+    /// Writes the history to the file at `path`
+    pub fn save<P>(&self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let data = serde_json::to_string(self).expect("History should always be serializable");
+        std::fs::write(path, data)
+    }
+
+    /// Records the images now shown on each monitor. Monitors showing a different image to the
+    /// current entry of their history get it added after that entry, discarding anything that
+    /// was ahead of it.
+    pub fn push(&mut self, images: &[PathBuf]) {
+        if self.monitors.len() < images.len() {
+            self.monitors.resize_with(images.len(), Default::default);
+        }
+        for (monitor, image) in self.monitors.iter_mut().zip(images) {
+            if monitor.entries.get(monitor.position) == Some(image) {
+                continue;
+            }
+            if !monitor.entries.is_empty() {
+                monitor.entries.truncate(monitor.position + 1);
+            }
+            monitor.entries.push(image.clone());
+            if monitor.entries.len() > MAX_HISTORY {
+                monitor.entries.remove(0);
+            }
+            monitor.position = monitor.entries.len() - 1;
+        }
+    }
+
+    /// Moves `monitor` back to the last image before the current one that `usable` accepts,
+    /// returning it if there was one
+    pub fn back<F>(&mut self, monitor: usize, usable: F) -> Option<PathBuf>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let history = self.monitors.get_mut(monitor)?;
+        let position = (0..history.position)
+            .rev()
+            .find(|k| usable(&history.entries[*k]))?;
+        history.position = position;
+        Some(history.entries[position].clone())
+    }
+
+    /// Moves `monitor` forward to the next image after the current one that `usable` accepts,
+    /// returning it if we weren't already at the newest one
+    pub fn forward<F>(&mut self, monitor: usize, usable: F) -> Option<PathBuf>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let history = self.monitors.get_mut(monitor)?;
+        let position =
+            (history.position + 1..history.entries.len()).find(|k| usable(&history.entries[*k]))?;
+        history.position = position;
+        Some(history.entries[position].clone())
+    }
+
+    /// The history of each monitor
+    pub fn monitors(&self) -> &[MonitorHistory] {
+        &self.monitors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn any(_: &Path) -> bool {
+        true
+    }
+
+    #[test]
+    fn back_and_forward() {
+        let mut history = History::default();
+        assert!(history.back(0, any).is_none());
+        history.push(&set(&["a", "x"]));
+        history.push(&set(&["b", "x"]));
+        history.push(&set(&["c", "y"]));
+
+        assert_eq!(history.back(0, any), Some("b".into()));
+        assert_eq!(history.back(0, any), Some("a".into()));
+        assert!(history.back(0, any).is_none());
+        assert_eq!(history.forward(0, any), Some("b".into()));
+        assert_eq!(history.forward(0, any), Some("c".into()));
+        assert!(history.forward(0, any).is_none());
+
+        // Each monitor only goes through what it has shown
+        assert_eq!(history.back(1, any), Some("x".into()));
+        assert!(history.back(1, any).is_none());
+    }
+
+    #[test]
+    fn unusable_images_are_skipped() {
+        let mut history = History::default();
+        for name in ["a", "banned", "b"] {
+            history.push(&set(&[name]));
+        }
+        let usable = |p: &Path| p != Path::new("banned");
+        assert_eq!(history.back(0, usable), Some("a".into()));
+        assert_eq!(history.forward(0, usable), Some("b".into()));
+        assert!(history.back(0, |p| p == Path::new("b")).is_none());
+        assert_eq!(history.monitors()[0].position, 2);
+    }
+
+    #[test]
+    fn push_discards_forward_entries() {
+        let mut history = History::default();
+        history.push(&set(&["a", "x"]));
+        history.push(&set(&["b", "x"]));
+        history.back(0, any);
+        history.push(&set(&["c", "y"]));
+
+        assert_eq!(history.monitors()[0].entries, set(&["a", "c"]));
+        assert_eq!(history.monitors()[0].position, 1);
+        assert_eq!(history.monitors()[1].entries, set(&["x", "y"]));
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut history = History::default();
+        for ii in 0..MAX_HISTORY + 10 {
+            history.push(&set(&[&ii.to_string()]));
+        }
+        let monitor = &history.monitors()[0];
+        assert_eq!(monitor.entries.len(), MAX_HISTORY);
+        assert_eq!(monitor.position, MAX_HISTORY - 1);
+        assert_eq!(monitor.entries[0], PathBuf::from("10"));
+    }
+}
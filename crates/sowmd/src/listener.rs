@@ -2,11 +2,17 @@ use interprocess::local_socket::{prelude::*, GenericFilePath, ListenerOptions, S
 use std::{
     io::{BufReader, Read, Write},
     path::Path,
-    sync::mpsc::Sender,
+    sync::mpsc::{channel, Sender},
 };
 
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
 use sowm_common::{packet::Packet, ClientMessage, Init, ServerMessage, SowmError};
 
+use crate::engine::Request;
+
 // Define a function that checks for errors in incoming connections. We'll use this to filter
 // through connections that fail on initialization for one reason or another.
 fn handle_error(conn: std::io::Result<Stream>) -> Option<Stream> {
@@ -19,14 +25,24 @@ fn handle_error(conn: std::io::Result<Stream>) -> Option<Stream> {
     }
 }
 
-pub fn setup_signal_handler(init: &Init) {
+/// Reloads the config on SIGHUP, and removes the socket and exits on the other termination
+/// signals
+pub fn setup_signal_handler(init: &Init, tx: Sender<Request>) {
     let path = init.socket_file.to_path_buf();
-    ctrlc::set_handler(move || {
-        println!("\nremoving socket: {}", path.display());
-        close_socket(&path).unwrap();
-        std::process::exit(0);
-    })
-    .expect("Signal handler should only be called once");
+    let mut signals =
+        Signals::new([SIGHUP, SIGINT, SIGTERM]).expect("Signal handler should only be set once");
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                // The engine only stops if the daemon is exiting anyway
+                let _ = tx.send(Request::internal(ClientMessage::Reload));
+                continue;
+            }
+            println!("\nremoving socket: {}", path.display());
+            close_socket(&path).unwrap();
+            std::process::exit(0);
+        }
+    });
 }
 
 pub fn close_socket<P>(path: P) -> Result<(), SowmError>
@@ -70,27 +86,46 @@ where
     Ok(listener)
 }
 
-pub fn listener(tx: Sender<ClientMessage>, listener: LocalSocketListener) -> ! {
+pub fn listener(tx: Sender<Request>, listener: LocalSocketListener) -> ! {
     for conn in listener.incoming().filter_map(handle_error) {
-        let mut conn = BufReader::new(conn);
-
-        // Get message
-        let mut header: [u8; 8] = [0; 8];
-        conn.read_exact(&mut header).unwrap();
-        let len = Packet::len_from_header(&header).unwrap();
-        let mut buf = vec![0; len];
-        conn.read_exact(&mut buf).unwrap();
-        let message: ClientMessage = ClientMessage::deserialize(&buf).unwrap();
-        println!("Client Sent: {message:#?}");
-        tx.send(message).unwrap();
-
-        // Send responce message
-        let message: ServerMessage = ServerMessage::Ok;
-        let data = message.serialize().unwrap();
-        let packet = Packet::new(data);
-        let bytes = packet.into_bytes();
-        conn.get_mut().write_all(&bytes).unwrap();
+        // A bad client only loses its own connection
+        if let Err(e) = handle_connection(conn, &tx) {
+            eprintln!("Dropped connection: {e}");
+        }
     }
 
     panic!("Ran out of listeners");
 }
+
+/// Reads one message from the client, passes it to the engine and sends back the reply
+fn handle_connection(conn: Stream, tx: &Sender<Request>) -> Result<(), String> {
+    let mut conn = BufReader::new(conn);
+
+    // Get message
+    let mut header: [u8; 8] = [0; 8];
+    conn.read_exact(&mut header)
+        .map_err(|e| format!("Failed to read the header: {e}"))?;
+    let len = Packet::len_from_header(&header).map_err(|e| e.to_string())?;
+    let mut buf = vec![0; len];
+    conn.read_exact(&mut buf)
+        .map_err(|e| format!("Failed to read the message: {e}"))?;
+    let message: ClientMessage = ClientMessage::deserialize(&buf).map_err(|e| e.to_string())?;
+    println!("Client Sent: {message:#?}");
+    let (reply_tx, reply_rx) = channel();
+    tx.send(Request {
+        message,
+        reply: reply_tx,
+    })
+    .map_err(|_| "The engine has stopped".to_string())?;
+
+    // Send responce message
+    let message: ServerMessage = reply_rx
+        .recv()
+        .map_err(|_| "The engine didn't reply".to_string())?;
+    let data = message.serialize().map_err(|e| e.to_string())?;
+    let packet = Packet::new(data);
+    let bytes = packet.into_bytes();
+    conn.get_mut()
+        .write_all(&bytes)
+        .map_err(|e| format!("Failed to send the reply: {e}"))
+}
@@ -0,0 +1,715 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use sowm_common::Order;
+
+/// Endlessly loops over a list of images in the given order
+///
+/// When shuffling, the images are reshuffled at the start of every pass. The last `recent` images
+/// of a pass are kept out of the first `recent` positions of the next one so they don't come up
+/// again straight away.
+pub struct LoopingIter {
+    arr: Vec<PathBuf>,
+    /// The images in `arr`, to tell if an image is there without looking through all of them
+    members: HashSet<PathBuf>,
+    ii: usize,
+    order: Order,
+    recent: usize,
+    /// Relative chance of each image being picked when ordering by weight, images that aren't in
+    /// here have a weight of 1
+    weights: HashMap<PathBuf, u32>,
+    /// If images of a sorted order were brought forward, so the images need sorting again at the
+    /// start of the next pass
+    resort: bool,
+}
+
+impl LoopingIter {
+    pub fn new(mut arr: Vec<PathBuf>, order: Order, recent: usize) -> Self {
+        sort_images(&mut arr, order);
+        LoopingIter {
+            members: arr.iter().cloned().collect(),
+            arr,
+            ii: 0,
+            order,
+            recent,
+            weights: HashMap::new(),
+            resort: false,
+        }
+    }
+
+    /// Adds an image, keeping the order of the rest. When shuffling it is placed somewhere in the
+    /// remainder of the current pass.
+    pub fn insert(&mut self, image: PathBuf) {
+        self.extend(vec![image]);
+    }
+
+    /// Removes an image, or every image under a directory, keeping the order of the rest
+    pub fn remove(&mut self, path: &Path) {
+        self.retain(|p| !p.starts_with(path));
+    }
+
+    /// Inserts and removes images so there are the same images as `images`, keeping the order of
+    /// the images that were already there
+    pub fn sync(&mut self, images: Vec<PathBuf>) {
+        let new: HashSet<&Path> = images.iter().map(|p| p.as_path()).collect();
+        self.retain(|p| new.contains(p));
+        self.extend(images);
+    }
+
+    /// Adds the images that aren't there yet, keeping the order of the rest. When shuffling they
+    /// are placed somewhere in the remainder of the current pass.
+    fn extend(&mut self, images: Vec<PathBuf>) {
+        let mut added: Vec<PathBuf> = images
+            .into_iter()
+            .filter(|p| self.members.insert(p.clone()))
+            .collect();
+        if added.is_empty() {
+            return;
+        }
+        let order = self.order;
+        let slots: Vec<(usize, PathBuf)> = match order {
+            Order::Random | Order::Weighted => {
+                self.arr.extend(added);
+                return;
+            }
+            Order::Shuffle => {
+                let mut rng = thread_rng();
+                let start = self.ii.min(self.arr.len());
+                let mut slots: Vec<_> = added
+                    .into_iter()
+                    .map(|p| (rng.gen_range(start..=self.arr.len()), p))
+                    .collect();
+                slots.sort_by_key(|(k, _)| *k);
+                slots
+            }
+            Order::Name | Order::Natural | Order::Mtime => {
+                added.sort_by(|a, b| image_cmp(order, a, b));
+                added
+                    .into_iter()
+                    .map(|p| {
+                        let k = self
+                            .arr
+                            .partition_point(|q| image_cmp(order, q, &p) == Ordering::Less);
+                        (k, p)
+                    })
+                    .collect()
+            }
+        };
+
+        // Put each image in front of the one at its index in a single pass
+        let old = std::mem::take(&mut self.arr);
+        let mut arr = Vec::with_capacity(old.len() + slots.len());
+        let mut slots = slots.into_iter().peekable();
+        let mut shift = 0;
+        for (k, image) in old.into_iter().enumerate() {
+            while let Some((slot, added)) = slots.next_if(|(slot, _)| *slot <= k) {
+                if slot < self.ii {
+                    shift += 1;
+                }
+                arr.push(added);
+            }
+            arr.push(image);
+        }
+        arr.extend(slots.map(|(_, p)| p));
+        self.arr = arr;
+        self.ii += shift;
+    }
+
+    /// Keeps only the images that `keep` accepts, in the same order and carrying on from the same
+    /// image
+    fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&Path) -> bool,
+    {
+        let (ii, mut k, mut before) = (self.ii, 0, 0);
+        let members = &mut self.members;
+        self.arr.retain(|p| {
+            let kept = keep(p);
+            if !kept {
+                members.remove(p);
+                if k < ii {
+                    before += 1;
+                }
+            }
+            k += 1;
+            kept
+        });
+        self.ii -= before;
+        let members = &self.members;
+        self.weights.retain(|p, _| members.contains(p));
+    }
+
+    /// Carries on from a previous order of the images and position in it, as far as the images
+    /// that are still around allow
+    pub fn restore(&mut self, saved: &[PathBuf], position: usize) {
+        let current: HashSet<PathBuf> = self.arr.iter().cloned().collect();
+        match self.order {
+            Order::Random | Order::Weighted => {}
+            Order::Shuffle => {
+                let saved_set: HashSet<&PathBuf> = saved.iter().collect();
+                let added: Vec<PathBuf> = self
+                    .arr
+                    .iter()
+                    .filter(|p| !saved_set.contains(p))
+                    .cloned()
+                    .collect();
+                self.ii = saved[..position.min(saved.len())]
+                    .iter()
+                    .filter(|p| current.contains(*p))
+                    .count();
+                self.arr = saved
+                    .iter()
+                    .filter(|p| current.contains(*p))
+                    .cloned()
+                    .collect();
+                for image in added.iter() {
+                    self.members.remove(image);
+                }
+                self.extend(added);
+            }
+            // Sorted orders can't change, so carry on from the image that would have been next
+            Order::Name | Order::Natural | Order::Mtime => {
+                let next = saved.iter().skip(position).find(|p| current.contains(*p));
+                if let Some(next) = next {
+                    self.ii = self.arr.iter().position(|p| p == next).unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    /// The order images are iterated in
+    pub fn order(&self) -> Order {
+        self.order
+    }
+
+    /// All images, in the order they are being iterated in
+    pub fn images(&self) -> &[PathBuf] {
+        &self.arr
+    }
+
+    /// Index of the next image to be shown
+    pub fn position(&self) -> usize {
+        self.ii
+    }
+
+    /// Sets how many images at the end of a pass are kept away from the start of the next one
+    pub fn set_recent(&mut self, recent: usize) {
+        self.recent = recent;
+    }
+
+    /// Sets the weight of every image to the result of `weight`
+    pub fn set_weights<F>(&mut self, weight: F)
+    where
+        F: Fn(&Path) -> u32,
+    {
+        self.weights = self.arr.iter().map(|p| (p.clone(), weight(p))).collect();
+    }
+
+    /// Picks a random image that `allowed` accepts with a chance proportional to its weight
+    fn weighted_choice<F>(&self, mut allowed: F) -> Option<PathBuf>
+    where
+        F: FnMut(&Path) -> bool,
+    {
+        let weights: Vec<u32> = self
+            .arr
+            .iter()
+            .map(|p| match allowed(p) {
+                true => self.weights.get(p).copied().unwrap_or(1),
+                false => 0,
+            })
+            .collect();
+        weighted_index(&weights, &mut thread_rng()).map(|ii| self.arr[ii].clone())
+    }
+
+    /// Gets the next image that isn't in `exclude`, falling back to any image if there is no
+    /// other choice
+    pub fn next_distinct(&mut self, exclude: &[PathBuf]) -> Option<PathBuf> {
+        match self.order {
+            Order::Random => {
+                let candidates: Vec<_> = self.arr.iter().filter(|p| !exclude.contains(p)).collect();
+                match candidates.choose(&mut thread_rng()) {
+                    Some(image) => Some((*image).clone()),
+                    None => self.next(),
+                }
+            }
+            Order::Weighted => self
+                .weighted_choice(|p| !exclude.iter().any(|e| e == p))
+                .or_else(|| self.next()),
+            Order::Shuffle => {
+                // Bring the next image that isn't excluded forward, the order is random anyway.
+                // Look in the rest of this pass, then in a new one.
+                for _ in 0..2 {
+                    if self.ii >= self.arr.len() {
+                        self.wrap();
+                    }
+                    if let Some(k) =
+                        (self.ii..self.arr.len()).find(|k| !exclude.contains(&self.arr[*k]))
+                    {
+                        self.arr.swap(self.ii, k);
+                        break;
+                    }
+                    if self.ii == 0 {
+                        break;
+                    }
+                    self.ii = self.arr.len();
+                }
+                self.next()
+            }
+            Order::Name | Order::Natural | Order::Mtime => {
+                // Skip over excluded images rather than moving them, so the pass stays sorted
+                let len = self.arr.len();
+                for _ in 0..len {
+                    if self.ii >= len {
+                        self.wrap();
+                    }
+                    if !exclude.contains(&self.arr[self.ii]) {
+                        break;
+                    }
+                    self.ii += 1;
+                }
+                self.next()
+            }
+        }
+    }
+
+    /// Gets the next image that isn't in `exclude` and that `fits` accepts. Falls back to
+    /// `next_distinct` if no image fits.
+    ///
+    /// For shuffled and sorted orders the image that fits is brought forward, so the images it
+    /// skips over still come up later in the pass.
+    pub fn next_fitting<F>(&mut self, exclude: &[PathBuf], mut fits: F) -> Option<PathBuf>
+    where
+        F: FnMut(&Path) -> bool,
+    {
+        let mut allowed = |p: &Path| !exclude.iter().any(|e| e == p) && fits(p);
+        match self.order {
+            Order::Random => {
+                let candidates: Vec<_> = self.arr.iter().filter(|p| allowed(p)).collect();
+                if let Some(image) = candidates.choose(&mut thread_rng()) {
+                    return Some((*image).clone());
+                }
+            }
+            Order::Weighted => {
+                if let Some(image) = self.weighted_choice(allowed) {
+                    return Some(image);
+                }
+            }
+            Order::Shuffle | Order::Name | Order::Natural | Order::Mtime => {
+                // Look in the rest of this pass, then in a new one
+                for _ in 0..2 {
+                    if self.ii >= self.arr.len() {
+                        self.wrap();
+                    }
+                    if let Some(k) = (self.ii..self.arr.len()).find(|k| allowed(&self.arr[*k])) {
+                        self.arr[self.ii..=k].rotate_right(1);
+                        self.resort = self.order != Order::Shuffle;
+                        return self.next();
+                    }
+                    if self.ii == 0 {
+                        break;
+                    }
+                    self.ii = self.arr.len();
+                }
+            }
+        }
+        self.next_distinct(exclude)
+    }
+
+    /// Starts a new pass over the images
+    fn wrap(&mut self) {
+        self.ii = 0;
+        if self.order != Order::Shuffle {
+            if std::mem::take(&mut self.resort) {
+                sort_images(&mut self.arr, self.order);
+            }
+            return;
+        }
+
+        let n = self.arr.len();
+        let recent = self.recent.min(n / 2);
+        let recent_images = self.arr[n - recent..].to_vec();
+        let mut rng = thread_rng();
+        self.arr.shuffle(&mut rng);
+
+        for ii in 0..recent {
+            if !recent_images.contains(&self.arr[ii]) {
+                continue;
+            }
+            let later: Vec<_> = (recent..n)
+                .filter(|jj| !recent_images.contains(&self.arr[*jj]))
+                .collect();
+            if let Some(jj) = later.choose(&mut rng) {
+                self.arr.swap(ii, *jj);
+            }
+        }
+    }
+}
+
+impl Iterator for LoopingIter {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.order {
+            Order::Random => return self.arr.choose(&mut thread_rng()).cloned(),
+            Order::Weighted => {
+                return self
+                    .weighted_choice(|_| true)
+                    .or_else(|| self.arr.choose(&mut thread_rng()).cloned())
+            }
+            _ => {}
+        }
+
+        if self.ii >= self.arr.len() {
+            self.wrap();
+        }
+        let image = self.arr.get(self.ii).cloned();
+        self.ii += 1;
+        image
+    }
+}
+
+/// Puts the images in the order they should be shown in
+fn sort_images(images: &mut [PathBuf], order: Order) {
+    match order {
+        Order::Shuffle => images.shuffle(&mut thread_rng()),
+        Order::Name => images.sort(),
+        Order::Natural => images.sort_by(|a, b| natural_cmp(a, b)),
+        Order::Mtime => images.sort_by_cached_key(|p| std::cmp::Reverse(modified(p))),
+        // Every image is picked at random when iterating, the order doesn't matter
+        Order::Random | Order::Weighted => {}
+    }
+}
+
+/// Picks a random index with a chance proportional to its weight, `None` if all weights are 0
+fn weighted_index<R: Rng>(weights: &[u32], rng: &mut R) -> Option<usize> {
+    let total: u64 = weights.iter().map(|w| *w as u64).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut target = rng.gen_range(0..total);
+    for (ii, w) in weights.iter().enumerate() {
+        if target < *w as u64 {
+            return Some(ii);
+        }
+        target -= *w as u64;
+    }
+    None
+}
+
+/// Compares two images by where they should go for sorted orders
+fn image_cmp(order: Order, a: &Path, b: &Path) -> Ordering {
+    match order {
+        Order::Name => a.cmp(b),
+        Order::Natural => natural_cmp(a, b),
+        Order::Mtime => modified(b).cmp(&modified(a)),
+        Order::Shuffle | Order::Random | Order::Weighted => Ordering::Equal,
+    }
+}
+
+/// Last modification time of the file, files that can't be read are treated as the oldest
+fn modified(path: &Path) -> SystemTime {
+    path.metadata()
+        .and_then(|m| m.modified())
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Compares paths so that runs of digits are compared by their numeric value
+fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    let a = a.to_string_lossy();
+    let b = b.to_string_lossy();
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Compare by length once leading zeros are gone so huge numbers can't overflow
+                let ord = x
+                    .trim_start_matches('0')
+                    .len()
+                    .cmp(&y.trim_start_matches('0').len())
+                    .then_with(|| x.trim_start_matches('0').cmp(y.trim_start_matches('0')))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Consumes a run of ascii digits from the iterator
+fn take_number<I>(chars: &mut std::iter::Peekable<I>) -> String
+where
+    I: Iterator<Item = char>,
+{
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        number.push(c);
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut images = paths(&[
+            "img10.jpg",
+            "img2.jpg",
+            "img1.jpg",
+            "a/img3.jpg",
+            "img02.jpg",
+        ]);
+        sort_images(&mut images, Order::Natural);
+        assert_eq!(
+            images,
+            paths(&[
+                "a/img3.jpg",
+                "img1.jpg",
+                "img2.jpg",
+                "img02.jpg",
+                "img10.jpg"
+            ])
+        );
+    }
+
+    #[test]
+    fn name_order_loops() {
+        let images = paths(&["c.jpg", "a.jpg", "b.jpg"]);
+        let iter = LoopingIter::new(images, Order::Name, 1);
+        let shown: Vec<_> = iter.take(4).collect();
+        assert_eq!(shown, paths(&["a.jpg", "b.jpg", "c.jpg", "a.jpg"]));
+    }
+
+    #[test]
+    fn random_order_picks_from_images() {
+        let images = paths(&["a.jpg", "b.jpg"]);
+        let iter = LoopingIter::new(images.clone(), Order::Random, 1);
+        for image in iter.take(20) {
+            assert!(images.contains(&image));
+        }
+    }
+
+    #[test]
+    fn reshuffle_keeps_recent_images_away_from_front() {
+        let images: Vec<_> = (0..6)
+            .map(|ii| PathBuf::from(format!("{ii}.jpg")))
+            .collect();
+        let mut iter = LoopingIter::new(images, Order::Shuffle, 2);
+        let mut previous: Vec<_> = (&mut iter).take(6).collect();
+        for _ in 0..100 {
+            let pass: Vec<_> = (&mut iter).take(6).collect();
+            let mut sorted = pass.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), 6, "Every image should be shown once per pass");
+            for image in pass[..2].iter() {
+                assert!(!previous[4..].contains(image), "Recent image shown again");
+            }
+            previous = pass;
+        }
+    }
+
+    #[test]
+    fn distinct_images_in_a_set() {
+        for order in [Order::Shuffle, Order::Random, Order::Weighted] {
+            let images = paths(&["a.jpg", "b.jpg", "c.jpg"]);
+            let mut iter = LoopingIter::new(images, order, 3);
+            for _ in 0..100 {
+                let mut set = Vec::new();
+                for _ in 0..3 {
+                    let image = iter.next_distinct(&set).unwrap();
+                    set.push(image);
+                }
+                set.sort();
+                set.dedup();
+                assert_eq!(set.len(), 3, "Duplicate image in set for {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn sorted_order_skips_excluded_images() {
+        let images = paths(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Name, 2);
+        // b.jpg is pinned to another monitor
+        let pinned = paths(&["b.jpg"]);
+        let shown: Vec<_> = (0..5)
+            .map(|_| iter.next_distinct(&pinned).unwrap())
+            .collect();
+        assert_eq!(shown, paths(&["a.jpg", "c.jpg", "d.jpg", "a.jpg", "c.jpg"]));
+        assert_eq!(iter.arr, paths(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]));
+
+        // Falls back to the excluded images when there is nothing else
+        let all = paths(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        assert!(iter.next_distinct(&all).is_some());
+    }
+
+    #[test]
+    fn shuffle_skips_an_excluded_end_of_pass() {
+        let images = paths(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Shuffle, 1);
+        iter.next();
+        iter.next();
+        // The rest of the pass is shown on other monitors
+        let exclude = iter.arr[2..].to_vec();
+        for _ in 0..10 {
+            let image = iter.next_distinct(&exclude).unwrap();
+            assert!(!exclude.contains(&image), "Showed excluded {image:?}");
+        }
+    }
+
+    #[test]
+    fn weighted_order_follows_weights() {
+        let images = paths(&["a.jpg", "b.jpg", "c.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Weighted, 1);
+        iter.set_weights(|p| if p == Path::new("c.jpg") { 0 } else { 1 });
+        for image in iter.take(100) {
+            assert_ne!(image, PathBuf::from("c.jpg"));
+        }
+
+        let mut counts = [0; 2];
+        let mut rng = thread_rng();
+        for _ in 0..10000 {
+            counts[weighted_index(&[1, 4], &mut rng).unwrap()] += 1;
+        }
+        assert!(
+            counts[1] > counts[0] * 3,
+            "Weights not respected: {counts:?}"
+        );
+    }
+
+    #[test]
+    fn fitting_images_come_first() {
+        let fits = |p: &Path| p.starts_with("wide");
+        for order in [Order::Shuffle, Order::Random, Order::Weighted, Order::Name] {
+            let images = paths(&["a.jpg", "b.jpg", "wide/c.jpg", "d.jpg"]);
+            let mut iter = LoopingIter::new(images, order, 1);
+            for _ in 0..10 {
+                assert_eq!(iter.next_fitting(&[], fits), Some("wide/c.jpg".into()));
+            }
+            assert!(iter.next_fitting(&[], |_| false).is_some());
+        }
+
+        // Images that were skipped over still come up in sorted order
+        let images = paths(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Name, 1);
+        let image = iter.next_fitting(&[], |p| p == Path::new("c.jpg"));
+        assert_eq!(image, Some("c.jpg".into()));
+        let shown: Vec<_> = iter.take(4).collect();
+        assert_eq!(shown, paths(&["a.jpg", "b.jpg", "d.jpg", "a.jpg"]));
+    }
+
+    #[test]
+    fn remove_keeps_position() {
+        let images = paths(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Name, 1);
+        iter.next();
+        iter.next();
+        iter.remove(Path::new("a.jpg"));
+        iter.remove(Path::new("d.jpg"));
+        let shown: Vec<_> = iter.take(3).collect();
+        assert_eq!(shown, paths(&["c.jpg", "b.jpg", "c.jpg"]));
+    }
+
+    #[test]
+    fn sync_keeps_order() {
+        let images = paths(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Name, 1);
+        iter.next();
+        iter.next();
+        iter.sync(paths(&["d.jpg", "b.jpg", "c.jpg", "bb.jpg"]));
+        assert_eq!(iter.images(), paths(&["b.jpg", "bb.jpg", "c.jpg", "d.jpg"]));
+        let shown: Vec<_> = iter.take(2).collect();
+        assert_eq!(shown, paths(&["bb.jpg", "c.jpg"]));
+    }
+
+    #[test]
+    fn empty_until_images_are_added() {
+        let mut iter = LoopingIter::new(paths(&["a.jpg"]), Order::Name, 1);
+        iter.remove(Path::new("a.jpg"));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_distinct(&[]), None);
+        iter.insert(PathBuf::from("b.jpg"));
+        assert_eq!(iter.next(), Some(PathBuf::from("b.jpg")));
+    }
+
+    #[test]
+    fn remove_directory() {
+        let images = paths(&["a/1.jpg", "a/2.jpg", "ab.jpg", "b/1.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Name, 1);
+        iter.remove(Path::new("a"));
+        assert_eq!(iter.images(), paths(&["ab.jpg", "b/1.jpg"]));
+    }
+
+    #[test]
+    fn insert_keeps_sorted_order() {
+        let images = paths(&["img1.jpg", "img3.jpg", "img10.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Natural, 1);
+        iter.next();
+        iter.next();
+        iter.insert(PathBuf::from("img2.jpg"));
+        iter.insert(PathBuf::from("img4.jpg"));
+        let shown: Vec<_> = iter.take(4).collect();
+        assert_eq!(
+            shown,
+            paths(&["img4.jpg", "img10.jpg", "img1.jpg", "img2.jpg"])
+        );
+    }
+
+    #[test]
+    fn restore_shuffled_position() {
+        let saved = paths(&["c.jpg", "a.jpg", "d.jpg", "b.jpg"]);
+        let images = paths(&["a.jpg", "b.jpg", "c.jpg", "e.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Shuffle, 1);
+        iter.restore(&saved, 2);
+
+        // d.jpg is gone and e.jpg has to come after the position
+        assert_eq!(iter.position(), 2);
+        assert_eq!(&iter.images()[..2], &paths(&["c.jpg", "a.jpg"]));
+        let rest: HashSet<_> = iter.images()[2..].iter().cloned().collect();
+        assert_eq!(rest, paths(&["b.jpg", "e.jpg"]).into_iter().collect());
+    }
+
+    #[test]
+    fn restore_sorted_position() {
+        let saved = paths(&["a.jpg", "b.jpg", "c.jpg"]);
+        let images = paths(&["a.jpg", "c.jpg", "d.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Name, 1);
+        iter.restore(&saved, 1);
+        assert_eq!(iter.next(), Some(PathBuf::from("c.jpg")));
+    }
+
+    #[test]
+    fn fewer_images_than_monitors() {
+        let images = paths(&["a.jpg"]);
+        let mut iter = LoopingIter::new(images, Order::Shuffle, 2);
+        let first = iter.next_distinct(&[]).unwrap();
+        let second = iter.next_distinct(std::slice::from_ref(&first)).unwrap();
+        assert_eq!(first, second);
+    }
+}
@@ -1,32 +1,60 @@
 use std::{process::exit, sync::mpsc::channel};
 
 use listener::{close_socket, open_socket, setup_signal_handler};
-use sowm_common::init;
 
+/// Converting images the backend can't show
+mod convert;
+/// Finding copies of the same image
+mod dupes;
 /// Engine to run the logic to update the wallpaper
 mod engine;
+/// History of the images that have been shown
+mod history;
 /// Listen for messages that get sent over the socket
 mod listener;
+/// Iterate over images in the configured order
+mod looping_iter;
+/// Finding out which outputs the monitors are
+mod monitors;
+/// Ratings given to images
+mod ratings;
+/// Saved state of the engine
+mod snapshot;
+/// Position of the sun for switching collections at sunrise and sunset
+mod solar;
+/// Working out when to next switch the wallpaper
+mod timer;
+/// Watching image directories for images being added and removed
+mod watcher;
 
 fn main() {
-    let init = match init() {
+    let loaded = match engine::load(&[]) {
         Err(e) => panic!("Init Error: {e}"),
         Ok(v) => v,
     };
+    let init = loaded.init();
 
     let socket_file = init.socket_file.clone();
 
-    setup_signal_handler(&init);
+    let (tx, rx) = channel();
+    setup_signal_handler(init, tx.clone());
     let listener = match open_socket(&socket_file) {
         Err(e) => panic!("Socket Error: {e}"),
         Ok(v) => v,
     };
 
-    let (tx, rx) = channel();
+    // Kept alive for as long as the config should be watched
+    let _config_watcher = match watcher::watch_config(&init.config_path, tx.clone()) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            eprintln!("Config file won't be watched: {e}");
+            None
+        }
+    };
     let _h1 = std::thread::spawn(move || listener::listener(tx, listener));
-    let h2 = std::thread::spawn(move || engine::run(rx, init));
+    let h2 = std::thread::spawn(move || engine::run(rx, loaded));
 
-    if let Err(_) = h2.join() {
+    if h2.join().is_err() {
         close_socket(&socket_file).unwrap();
         eprintln!("Engine thread paniced");
         exit(1);
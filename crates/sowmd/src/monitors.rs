@@ -0,0 +1,93 @@
+use sowm_common::header::Dimensions;
+
+/// A monitor as xrandr sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub name: String,
+    /// Resolution of the monitor in pixels
+    pub size: Dimensions,
+}
+
+/// Gets the monitors, in the same order feh assigns images to them. Empty if they couldn't be
+/// found.
+pub fn outputs() -> Vec<Output> {
+    let output = std::process::Command::new("xrandr")
+        .arg("--listmonitors")
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            parse_list_monitors(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => {
+            eprintln!(
+                "Failed to list monitors: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            Vec::new()
+        }
+        Err(e) => {
+            eprintln!("Failed to run xrandr to list monitors: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Parses the output of `xrandr --listmonitors`, which looks like
+///
+/// ```text
+/// Monitors: 2
+///  0: +*DP-1 2560/597x1440/336+0+0  DP-1
+///  1: +HDMI-1 1920/527x1080/296+2560+0  HDMI-1
+/// ```
+fn parse_list_monitors(output: &str) -> Vec<Output> {
+    let mut monitors: Vec<(usize, Output)> = output
+        .lines()
+        .filter_map(|line| {
+            let (index, rest) = line.trim().split_once(':')?;
+            let index = index.parse().ok()?;
+            let mut fields = rest.split_whitespace();
+            // Sizes are given as width/millimetres x height/millimetres+x+y
+            let geometry = fields.nth(1)?;
+            let (width, rest) = geometry.split_once('/')?;
+            let (_, height) = rest.split_once('x')?;
+            let (height, _) = height.split_once('/')?;
+            let name = fields.last()?;
+            let size = Dimensions {
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+            };
+            Some((
+                index,
+                Output {
+                    name: name.to_string(),
+                    size,
+                },
+            ))
+        })
+        .collect();
+    monitors.sort_by_key(|(index, _)| *index);
+    monitors.into_iter().map(|(_, output)| output).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_xrandr_monitors() {
+        let output = "Monitors: 2\n \
+                      1: +HDMI-1 1920/527x1080/296+2560+0  HDMI-1\n \
+                      0: +*DP-1 2560/597x1440/336+0+0  DP-1\n";
+        let outputs = parse_list_monitors(output);
+        let names: Vec<&str> = outputs.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, ["DP-1", "HDMI-1"]);
+        assert_eq!(
+            outputs[1].size,
+            Dimensions {
+                width: 1920,
+                height: 1080
+            }
+        );
+        assert!(parse_list_monitors("").is_empty());
+    }
+}
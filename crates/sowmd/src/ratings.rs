@@ -0,0 +1,77 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sowm_common::MAX_RATING;
+
+/// Rating used for images that haven't been rated
+const DEFAULT_RATING: u8 = 3;
+
+/// Ratings that have been given to images
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ratings {
+    ratings: HashMap<PathBuf, u8>,
+}
+
+impl Ratings {
+    /// Loads the ratings from the file at `path`, returning no ratings if it can't be read
+    pub fn load<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the ratings to the file at `path`
+    pub fn save<P>(&self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let data = serde_json::to_string(self).expect("Ratings should always be serializable");
+        std::fs::write(path, data)
+    }
+
+    /// Sets the rating of an image, returns false if the rating is out of range
+    pub fn rate(&mut self, path: PathBuf, rating: u8) -> bool {
+        if !(1..=MAX_RATING).contains(&rating) {
+            return false;
+        }
+        self.ratings.insert(path, rating);
+        true
+    }
+
+    /// Removes the rating of an image
+    pub fn unrate(&mut self, path: &Path) {
+        self.ratings.remove(path);
+    }
+
+    /// How likely the image is to be picked relative to others when picking by rating
+    pub fn weight(&self, path: &Path) -> u32 {
+        self.ratings
+            .get(path)
+            .copied()
+            .unwrap_or(DEFAULT_RATING)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rating_range() {
+        let mut ratings = Ratings::default();
+        assert!(!ratings.rate("a.jpg".into(), 0));
+        assert!(!ratings.rate("a.jpg".into(), MAX_RATING + 1));
+        assert!(ratings.rate("a.jpg".into(), MAX_RATING));
+        assert_eq!(ratings.weight(Path::new("a.jpg")), MAX_RATING as u32);
+        ratings.unrate(Path::new("a.jpg"));
+        assert_eq!(ratings.weight(Path::new("a.jpg")), DEFAULT_RATING as u32);
+    }
+}
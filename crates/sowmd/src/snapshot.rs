@@ -0,0 +1,66 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use sowm_common::Order;
+
+/// Everything needed to pick up where the engine left off after the daemon restarts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// If wallpapers were being cycled, used for monitors missing from `monitors`
+    pub running: bool,
+    /// Collection the images were from
+    #[serde(default)]
+    pub collection: String,
+    /// Description of the schedule rule that applied, so a collection picked by hand can be kept
+    #[serde(default)]
+    pub rule: Option<String>,
+    /// Order the images were being iterated in
+    pub order: Order,
+    /// Images in the order they were being shown
+    pub images: Vec<PathBuf>,
+    /// Index in `images` of the next image to be shown
+    pub position: usize,
+    /// Images on each monitor
+    pub current: Vec<PathBuf>,
+    /// Images pinned to each monitor
+    pub pins: Vec<Option<PathBuf>>,
+    /// When the wallpaper was last switched by the timer, used for monitors missing from
+    /// `monitors`
+    pub last_switch: SystemTime,
+    /// State of each monitor's timer
+    #[serde(default)]
+    pub monitors: Vec<MonitorSnapshot>,
+}
+
+/// State of one monitor's timer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MonitorSnapshot {
+    /// If the monitor was being cycled
+    pub running: bool,
+    /// When the monitor was last switched by the timer
+    pub last_switch: SystemTime,
+}
+
+impl Snapshot {
+    /// Loads the snapshot from the file at `path`, if there is a valid one
+    pub fn load<P>(path: P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+    }
+
+    /// Writes the snapshot to the file at `path`
+    pub fn save<P>(&self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let data = serde_json::to_string(self).expect("Snapshot should always be serializable");
+        std::fs::write(path, data)
+    }
+}
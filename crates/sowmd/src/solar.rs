@@ -0,0 +1,162 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+
+/// Altitude of the sun's centre at sunrise and sunset in degrees, accounting for refraction and
+/// the size of the sun's disc
+const SUNRISE_ALTITUDE: f64 = -0.833;
+/// Altitude of the sun in degrees at the start and end of civil twilight
+const CIVIL_TWILIGHT_ALTITUDE: f64 = -6.0;
+/// Julian date of 2000-01-01 12:00 UTC
+const J2000: f64 = 2451545.0;
+/// Julian date of the unix epoch
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+/// Axial tilt of the earth in degrees
+const OBLIQUITY: f64 = 23.4397;
+
+/// Part of the day, depending on where the sun is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The sun is up
+    Day,
+    /// Civil twilight, either before sunrise or after sunset
+    Dusk,
+    /// The sun is far enough below the horizon that it is dark
+    Night,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Phase::Day => "day",
+            Phase::Dusk => "dusk",
+            Phase::Night => "night",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Which part of the day it is at `time` for the given location, in degrees with north and east
+/// positive
+pub fn phase(time: DateTime<Utc>, latitude: f64, longitude: f64) -> Phase {
+    let altitude = sun_altitude(time, latitude, longitude);
+    if altitude > SUNRISE_ALTITUDE {
+        Phase::Day
+    } else if altitude > CIVIL_TWILIGHT_ALTITUDE {
+        Phase::Dusk
+    } else {
+        Phase::Night
+    }
+}
+
+/// Altitude of the sun above the horizon in degrees at `time` for the given location
+fn sun_altitude(time: DateTime<Utc>, latitude: f64, longitude: f64) -> f64 {
+    let jd = utc_to_julian(time);
+    // Use the solar noon closest to the time so the hour angle stays within half a day
+    let days = (jd - J2000 + longitude / 360.0).round();
+    let sun = Sun::new(days, longitude);
+    let hour_angle = 360.0 * (jd - sun.transit);
+
+    let (lat, dec, ha) = (
+        latitude.to_radians(),
+        sun.declination.to_radians(),
+        hour_angle.to_radians(),
+    );
+    (lat.sin() * dec.sin() + lat.cos() * dec.cos() * ha.cos())
+        .asin()
+        .to_degrees()
+}
+
+/// Position of the sun around solar noon of one day, using the approximations from the sunrise
+/// equation which are accurate to around a minute
+struct Sun {
+    /// Julian date of solar noon
+    transit: f64,
+    /// Declination in degrees
+    declination: f64,
+}
+
+impl Sun {
+    /// `days` is the number of days since 2000-01-01
+    fn new(days: f64, longitude: f64) -> Self {
+        let mean_solar_time = days + 0.0008 - longitude / 360.0;
+        let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+        let m = mean_anomaly.to_radians();
+        let centre = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+        let ecliptic_longitude = (mean_anomaly + centre + 180.0 + 102.9372).rem_euclid(360.0);
+        let l = ecliptic_longitude.to_radians();
+
+        let transit = J2000 + mean_solar_time + 0.0053 * m.sin() - 0.0069 * (2.0 * l).sin();
+        let declination = (l.sin() * OBLIQUITY.to_radians().sin()).asin().to_degrees();
+        Sun {
+            transit,
+            declination,
+        }
+    }
+}
+
+fn utc_to_julian(time: DateTime<Utc>) -> f64 {
+    time.timestamp_millis() as f64 / 86_400_000.0 + UNIX_EPOCH_JD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: (f64, f64) = (51.5074, -0.1278);
+    const SYDNEY: (f64, f64) = (-33.8688, 151.2093);
+    const TROMSO: (f64, f64) = (69.6492, 18.9553);
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    /// Asserts the phase five minutes either side of `time`, where it should change from `before`
+    /// to `after`
+    fn assert_change((lat, lon): (f64, f64), time: &str, before: Phase, after: Phase) {
+        let time = utc(time);
+        let margin = chrono::Duration::minutes(5);
+        assert_eq!(phase(time - margin, lat, lon), before, "Before {time}");
+        assert_eq!(phase(time + margin, lat, lon), after, "After {time}");
+    }
+
+    #[test]
+    fn london_solstices() {
+        assert_change(LONDON, "2024-06-21T03:43:00Z", Phase::Dusk, Phase::Day);
+        assert_change(LONDON, "2024-06-21T20:21:00Z", Phase::Day, Phase::Dusk);
+
+        assert_change(LONDON, "2024-12-21T07:24:00Z", Phase::Night, Phase::Dusk);
+        assert_change(LONDON, "2024-12-21T08:04:00Z", Phase::Dusk, Phase::Day);
+        assert_change(LONDON, "2024-12-21T15:54:00Z", Phase::Day, Phase::Dusk);
+        assert_change(LONDON, "2024-12-21T16:34:00Z", Phase::Dusk, Phase::Night);
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        // 05:42 and 20:05 local time, which is UTC+11
+        assert_change(SYDNEY, "2024-12-20T18:42:00Z", Phase::Dusk, Phase::Day);
+        assert_change(SYDNEY, "2024-12-21T09:05:00Z", Phase::Day, Phase::Dusk);
+    }
+
+    #[test]
+    fn polar_night_and_midnight_sun() {
+        let (lat, lon) = TROMSO;
+        // The sun doesn't rise, but there is still twilight around noon
+        assert_eq!(phase(utc("2024-12-21T10:45:00Z"), lat, lon), Phase::Dusk);
+        assert_eq!(phase(utc("2024-12-21T22:45:00Z"), lat, lon), Phase::Night);
+
+        // The sun doesn't set
+        assert_eq!(phase(utc("2024-06-21T10:45:00Z"), lat, lon), Phase::Day);
+        assert_eq!(phase(utc("2024-06-21T23:00:00Z"), lat, lon), Phase::Day);
+    }
+
+    #[test]
+    fn phases_through_a_day() {
+        let (lat, lon) = LONDON;
+        assert_eq!(phase(utc("2024-12-21T03:00:00Z"), lat, lon), Phase::Night);
+        assert_eq!(phase(utc("2024-12-21T07:45:00Z"), lat, lon), Phase::Dusk);
+        assert_eq!(phase(utc("2024-12-21T12:00:00Z"), lat, lon), Phase::Day);
+        assert_eq!(phase(utc("2024-12-21T16:15:00Z"), lat, lon), Phase::Dusk);
+        assert_eq!(phase(utc("2024-12-21T20:00:00Z"), lat, lon), Phase::Night);
+    }
+}
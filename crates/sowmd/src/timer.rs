@@ -0,0 +1,112 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeDelta, TimeZone};
+use sowm_common::SwitchTiming;
+
+/// When the wallpaper should next be switched after it was switched at `last`, `None` if it never
+/// should be
+pub fn next_switch_time(timing: &SwitchTiming, last: SystemTime) -> Option<SystemTime> {
+    next_switch(timing, DateTime::<Local>::from(last)).map(SystemTime::from)
+}
+
+/// When the wallpaper should next be switched after it was switched at `last`, clock aligned
+/// timings follow the wall clock of `last`'s time zone
+pub fn next_switch<Tz: TimeZone>(
+    timing: &SwitchTiming,
+    last: DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    match timing {
+        SwitchTiming::Interval(interval) => {
+            last.checked_add_signed(TimeDelta::from_std(*interval).ok()?)
+        }
+        SwitchTiming::Aligned(interval) => {
+            let local = last.naive_local();
+            let midnight = local.date().and_time(NaiveTime::MIN);
+            let step = TimeDelta::from_std(*interval)
+                .ok()?
+                .max(TimeDelta::seconds(1));
+            let steps = (local - midnight).num_seconds() / step.num_seconds() + 1;
+            // Start counting again each midnight so intervals that don't divide a day stay aligned
+            let next = (midnight + step * steps as i32).min(midnight + TimeDelta::days(1));
+            resolve(&last.timezone(), next)
+        }
+        SwitchTiming::Cron(cron) => resolve(&last.timezone(), cron.next_after(last.naive_local())?),
+    }
+}
+
+/// Turns a wall clock time into an actual time
+fn resolve<Tz: TimeZone>(tz: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    tz.from_local_datetime(&time)
+        .earliest()
+        // The time was skipped when the clocks went forward, so use the time an hour later
+        .or_else(|| {
+            tz.from_local_datetime(&(time + TimeDelta::hours(1)))
+                .earliest()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{FixedOffset, Utc};
+
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn interval_counts_from_last_switch() {
+        let timing = SwitchTiming::Interval(Duration::from_secs(30 * 60));
+        assert_eq!(
+            next_switch(&timing, utc("2025-01-01T10:07:13Z")),
+            Some(utc("2025-01-01T10:37:13Z"))
+        );
+    }
+
+    #[test]
+    fn aligned_interval_follows_the_clock() {
+        let timing = SwitchTiming::Aligned(Duration::from_secs(30 * 60));
+        assert_eq!(
+            next_switch(&timing, utc("2025-01-01T10:07:13Z")),
+            Some(utc("2025-01-01T10:30:00Z"))
+        );
+        assert_eq!(
+            next_switch(&timing, utc("2025-01-01T10:30:00Z")),
+            Some(utc("2025-01-01T11:00:00Z"))
+        );
+
+        // 7 hours doesn't divide a day, so the last switch of the day is cut short
+        let timing = SwitchTiming::Aligned(Duration::from_secs(7 * 60 * 60));
+        assert_eq!(
+            next_switch(&timing, utc("2025-01-01T21:00:00Z")),
+            Some(utc("2025-01-02T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn aligned_to_local_time() {
+        let tz = FixedOffset::east_opt(5 * 60 * 60 + 30 * 60).unwrap();
+        let last = utc("2025-01-01T10:07:00Z").with_timezone(&tz);
+        let timing = SwitchTiming::Aligned(Duration::from_secs(60 * 60));
+        assert_eq!(
+            next_switch(&timing, last).map(|t| t.to_utc()),
+            Some(utc("2025-01-01T10:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn cron_uses_the_next_match() {
+        let timing = SwitchTiming::Cron("0 9 * * mon-fri".parse().unwrap());
+        // 2025-01-03 is a friday
+        assert_eq!(
+            next_switch(&timing, utc("2025-01-03T09:00:00Z")),
+            Some(utc("2025-01-06T09:00:00Z"))
+        );
+
+        let never = SwitchTiming::Cron("0 0 30 2 *".parse().unwrap());
+        assert_eq!(next_switch(&never, utc("2025-01-01T00:00:00Z")), None);
+    }
+}
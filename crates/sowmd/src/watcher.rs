@@ -0,0 +1,244 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use notify::{
+    event::{AccessKind, AccessMode, CreateKind, ModifyKind},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use sowm_common::{scan::ScanDir, ClientMessage};
+use walkdir::WalkDir;
+
+use crate::engine::Request;
+
+/// How often to try watching directories that weren't there again
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A change to the images in a watched directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// An image was added
+    Added(PathBuf),
+    /// A file or directory was removed, so any images at or under the path are gone
+    Removed(PathBuf),
+}
+
+/// Watches directories of images for images being added and removed
+pub struct DirWatcher {
+    /// Watching stops when this is dropped
+    watcher: RecommendedWatcher,
+    rx: Receiver<notify::Result<Event>>,
+    /// Directories being watched and which files in them are images
+    dirs: Vec<ScanDir>,
+    /// Directories that couldn't be watched, such as shares that aren't mounted
+    missing: Vec<PathBuf>,
+    last_retry: Instant,
+}
+
+impl DirWatcher {
+    /// Starts watching `dirs` and everything under them for images. Directories that can't be
+    /// watched are tried again every so often.
+    pub fn new(dirs: Vec<ScanDir>) -> notify::Result<Self> {
+        let (tx, rx) = channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        let mut missing = Vec::new();
+        for dir in dirs.iter() {
+            if let Err(e) = watcher.watch(&dir.path, RecursiveMode::Recursive) {
+                eprintln!(
+                    "Failed to watch {}, trying again later: {e}",
+                    dir.path.display()
+                );
+                missing.push(dir.path.clone());
+            }
+        }
+        Ok(DirWatcher {
+            watcher,
+            rx,
+            dirs,
+            missing,
+            last_retry: Instant::now(),
+        })
+    }
+
+    /// Changes to the images since the last call
+    pub fn changes(&mut self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for event in self.rx.try_iter() {
+            match event {
+                Ok(event) => {
+                    // A watched directory that is removed or unmounted needs watching again when
+                    // it comes back
+                    if matches!(event.kind, EventKind::Remove(_)) {
+                        for path in event.paths.iter() {
+                            if self.dirs.iter().any(|d| d.path == *path)
+                                && !self.missing.contains(path)
+                            {
+                                self.missing.push(path.clone());
+                            }
+                        }
+                    }
+                    changes.extend(event_changes(&event, &self.dirs));
+                }
+                Err(e) => eprintln!("Error watching image directories: {e}"),
+            }
+        }
+        if !self.missing.is_empty() && self.last_retry.elapsed() >= RETRY_INTERVAL {
+            self.last_retry = Instant::now();
+            changes.extend(self.watch_missing());
+        }
+        changes
+    }
+
+    /// Starts watching the directories that have come back, their images count as added
+    fn watch_missing(&mut self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        self.missing.retain(|dir| {
+            if !dir.is_dir() || self.watcher.watch(dir, RecursiveMode::Recursive).is_err() {
+                return true;
+            }
+            println!("{} is available again", dir.display());
+            changes.extend(path_changes(dir, &self.dirs));
+            false
+        });
+        changes
+    }
+}
+
+/// Asks the engine to reload whenever the config file at `path` is written. Watching stops when
+/// the returned watcher is dropped.
+pub fn watch_config(path: &Path, tx: Sender<Request>) -> notify::Result<RecommendedWatcher> {
+    let config = path.to_path_buf();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if is_config_written(&event, &config) {
+            // The engine only stops if the daemon is exiting anyway
+            let _ = tx.send(Request::internal(ClientMessage::Reload));
+        }
+    })?;
+    // Editors often replace the file rather than writing to it, so watch the directory
+    let dir = path.parent().unwrap_or(path);
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// If the event is the config file having been written or moved into place
+fn is_config_written(event: &Event, config: &Path) -> bool {
+    let written = matches!(
+        event.kind,
+        EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Modify(ModifyKind::Name(_))
+    );
+    written && event.paths.iter().any(|p| p == config)
+}
+
+/// Works out how an event changed the images, going by what is on disk now
+fn event_changes(event: &Event, dirs: &[ScanDir]) -> Vec<Change> {
+    match event.kind {
+        // New files are picked up when they are closed so half written images aren't shown
+        EventKind::Create(CreateKind::File) | EventKind::Modify(ModifyKind::Data(_)) => Vec::new(),
+        EventKind::Create(_)
+        | EventKind::Modify(ModifyKind::Name(_))
+        | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        | EventKind::Remove(_) => event
+            .paths
+            .iter()
+            .flat_map(|p| path_changes(p, dirs))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn path_changes(path: &Path, dirs: &[ScanDir]) -> Vec<Change> {
+    // Watched directories can be inside each other, an image only needs one of them to want it
+    let roots: Vec<&ScanDir> = dirs.iter().filter(|d| path.starts_with(&d.path)).collect();
+    let is_image = |p: &Path| roots.iter().any(|d| d.options.is_image(&d.path, p));
+    if roots.is_empty() {
+        return Vec::new();
+    }
+    if path.is_dir() {
+        // A directory moved in won't have events for the images inside it
+        WalkDir::new(path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .map(|e| e.into_path())
+            .filter(|p| p.is_file() && is_image(p))
+            .map(Change::Added)
+            .collect()
+    } else if path.is_file() {
+        match is_image(path) {
+            true => vec![Change::Added(path.to_path_buf())],
+            false => Vec::new(),
+        }
+    } else {
+        vec![Change::Removed(path.to_path_buf())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::{DataChange, RemoveKind, RenameMode};
+    use sowm_common::scan::{ScanOptions, ScanSettings};
+
+    use super::*;
+
+    #[test]
+    fn changes_follow_the_filesystem() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let nested = dir.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        let image = dir.join("a.jpg");
+        let text = dir.join("notes.txt");
+        let nested_image = nested.join("b.png");
+        for path in [&image, &text, &nested_image] {
+            std::fs::write(path, b"data").unwrap();
+        }
+
+        let settings = ScanSettings {
+            skip_hidden: true,
+            ..Default::default()
+        };
+        let dirs = [ScanDir {
+            path: dir.to_path_buf(),
+            options: ScanOptions::new(&settings, vec!["jpg".into(), "png".into()]).unwrap(),
+        }];
+        let event =
+            |kind, path: &Path| event_changes(&Event::new(kind).add_path(path.into()), &dirs);
+        let closed = EventKind::Access(AccessKind::Close(AccessMode::Write));
+        assert_eq!(event(closed, &image), [Change::Added(image.clone())]);
+        assert!(event(closed, &text).is_empty());
+        assert!(event(EventKind::Create(CreateKind::File), &image).is_empty());
+
+        let moved_in = EventKind::Modify(ModifyKind::Name(RenameMode::To));
+        assert_eq!(event(moved_in, &nested), [Change::Added(nested_image)]);
+
+        std::fs::remove_dir_all(&nested).unwrap();
+        let removed = EventKind::Remove(RemoveKind::Folder);
+        assert_eq!(event(removed, &nested), [Change::Removed(nested.clone())]);
+    }
+
+    #[test]
+    fn config_writes() {
+        let path = "/home/user/.config/sowm/config.toml";
+        let config = Path::new(path);
+        let written = |kind, path: &str| {
+            let event = Event::new(kind).add_path(path.into());
+            is_config_written(&event, config)
+        };
+        let closed = EventKind::Access(AccessKind::Close(AccessMode::Write));
+        assert!(written(closed, path));
+        assert!(written(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            path
+        ));
+        assert!(!written(closed, "/home/user/.config/sowm/other.toml"));
+        assert!(!written(
+            EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+            path
+        ));
+    }
+}